@@ -0,0 +1,144 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::notifier::Notifier;
+use crate::report::{format_count, format_usd};
+use crate::summary::SummaryRow;
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Serialize, Deserialize};
+use tokio_rusqlite::Connection;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertScope {
+    All,
+    App,
+    Package,
+    Country,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    Units,
+    NetRevenue,
+    // Units refunded
+    Refunds,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertPeriod {
+    Day,
+    Week,
+    Month,
+    // Milestones, like 10,000 units sold since the release, fire only once
+    Lifetime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlertRule {
+    pub id: Option<i64>,
+    pub name: String,
+    pub enabled: bool,
+    pub scope: AlertScope,
+    // Appid, packageid or country code, unused for `All`
+    pub scope_key: Option<String>,
+    pub metric: AlertMetric,
+    pub period: AlertPeriod,
+    pub threshold: f64,
+    // Notification body, a default one is used when empty
+    pub message: Option<String>,
+    // Key of the period the rule last fired in, see `period_key`
+    pub last_fired: Option<String>,
+}
+
+
+// Checks every enabled rule against the current period, each rule fires at most once per period
+pub async fn evaluate_rules(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
+    let today = Local::now().date_naive();
+    let rules: Vec<AlertRule> = database::get_alert_rules(connection).await?
+        .into_iter()
+        .filter(|r| r.enabled && r.last_fired.as_deref() != Some(period_key(r.period, today).as_str()))
+        .collect();
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let to = app::format_date(today);
+    for rule in rules.iter() {
+        // Totals are summed by the database, a lifetime rule doesn't load the whole history
+        let scope_key = match rule.scope {
+            AlertScope::All => None,
+            _ => match rule.scope_key.as_deref().map(str::trim) {
+                Some(key) if !key.is_empty() => Some(key.to_string()),
+                _ => continue,
+            },
+        };
+        let from = period_start(rule.period, today).map(app::format_date);
+        let totals = database::get_alert_totals(connection, rule.scope, scope_key, from, to.clone()).await?;
+        let value = metric_value(&totals, rule.metric);
+        if value < rule.threshold {
+            continue;
+        }
+
+        let body = match rule.message.as_deref().map(str::trim) {
+            Some(message) if !message.is_empty() => message.to_string(),
+            _ => default_message(rule, value),
+        };
+        log::info!("Alert \"{}\" fired: {}", rule.name, body);
+        notifier.notify(&rule.name, &body);
+
+        if let Some(id) = rule.id {
+            database::set_alert_rule_last_fired(connection, id, period_key(rule.period, today)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+
+// First day of the period containing `today`, `None` for lifetime
+fn period_start(period: AlertPeriod, today: NaiveDate) -> Option<NaiveDate> {
+    match period {
+        AlertPeriod::Day => Some(today),
+        AlertPeriod::Week => Some(today - Duration::days(today.weekday().num_days_from_monday() as i64)),
+        AlertPeriod::Month => Some(today.with_day(1).unwrap_or(today)),
+        AlertPeriod::Lifetime => None,
+    }
+}
+
+
+fn period_key(period: AlertPeriod, today: NaiveDate) -> String {
+    match period {
+        AlertPeriod::Day => app::format_date(today),
+        AlertPeriod::Week => format!("{}-W{:02}", today.iso_week().year(), today.iso_week().week()),
+        AlertPeriod::Month => today.format("%Y/%m").to_string(),
+        AlertPeriod::Lifetime => "lifetime".into(),
+    }
+}
+
+
+fn metric_value(summary: &SummaryRow, metric: AlertMetric) -> f64 {
+    match metric {
+        AlertMetric::Units => summary.net_units_sold as f64,
+        AlertMetric::NetRevenue => summary.net_sales_usd,
+        AlertMetric::Refunds => summary.gross_units_returned as f64,
+    }
+}
+
+
+fn default_message(rule: &AlertRule, value: f64) -> String {
+    let value = match rule.metric {
+        AlertMetric::Units => format!("{} units sold", format_count(value as i64)),
+        AlertMetric::NetRevenue => format!("{} net revenue", format_usd(value)),
+        AlertMetric::Refunds => format!("{} units refunded", format_count(value as i64)),
+    };
+    let period = match rule.period {
+        AlertPeriod::Day => "today",
+        AlertPeriod::Week => "this week",
+        AlertPeriod::Month => "this month",
+        AlertPeriod::Lifetime => "since the release",
+    };
+    format!("{} {}", value, period)
+}
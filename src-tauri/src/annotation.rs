@@ -0,0 +1,101 @@
+use crate::app::{self, ErrorType};
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationCategory {
+    Festival,
+    Update,
+    Streamer,
+    Sale,
+    Other,
+}
+
+// Something that happened between two days and may explain the numbers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Annotation {
+    pub id: Option<i64>,
+    pub from_date: String,
+    pub to_date: String,
+    // Every app when empty
+    pub appid: Option<i64>,
+    pub label: String,
+    pub category: AnnotationCategory,
+}
+
+// CSV line, only the start date and the label are required
+#[derive(Deserialize)]
+struct AnnotationRecord {
+    from_date: String,
+    to_date: Option<String>,
+    appid: Option<i64>,
+    label: String,
+    category: Option<String>,
+}
+
+
+// Dates are stored as `yyyy/MM/dd` like the sales, single day annotations end the day they start
+pub fn normalize(annotation: Annotation) -> Result<Annotation, ErrorType> {
+    let from = app::parse_date(&annotation.from_date)?;
+    let to = match annotation.to_date.trim() {
+        "" => from,
+        to_date => app::parse_date(to_date)?,
+    };
+    if to < from {
+        return Err(ErrorType::BadFormatting(format!("The annotation \"{}\" ends before it starts", annotation.label)));
+    }
+    if annotation.label.trim().is_empty() {
+        return Err(ErrorType::BadFormatting("Annotations need a label".into()));
+    }
+
+    Ok(Annotation {
+        from_date: app::format_date(from),
+        to_date: app::format_date(to),
+        label: annotation.label.trim().to_string(),
+        ..annotation
+    })
+}
+
+
+// Reads `from_date,to_date,appid,label,category` lines, the whole file is rejected on the first bad line
+pub fn read_csv(path: &str) -> Result<Vec<Annotation>, ErrorType> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(Path::new(path))
+        .map_err(|e| ErrorType::BadFormatting(format!("Could not read {}: {}", path, e)))?;
+
+    let mut annotations = Vec::new();
+    for (i, record) in reader.deserialize::<AnnotationRecord>().enumerate() {
+        // The header is line 1
+        let line = i + 2;
+        let record = record.map_err(|e| ErrorType::BadFormatting(format!("Line {}: {}", line, e)))?;
+        let category = match record.category.as_deref().map(str::to_lowercase).as_deref() {
+            None | Some("") => AnnotationCategory::Other,
+            Some(category) => serde_json::from_value(serde_json::Value::String(category.to_string()))
+                .map_err(|_| ErrorType::BadFormatting(format!("Line {}: unknown category {}", line, category)))?,
+        };
+        let annotation = normalize(Annotation {
+            id: None,
+            from_date: record.from_date,
+            to_date: record.to_date.unwrap_or_default(),
+            appid: record.appid,
+            label: record.label,
+            category,
+        })
+        .map_err(|e| ErrorType::BadFormatting(format!("Line {}: {}", line, e)))?;
+        annotations.push(annotation);
+    }
+
+    Ok(annotations)
+}
+
+
+// Labels of the annotations covering `date`, for exports
+pub fn labels_on(annotations: &[Annotation], date: &str) -> Vec<String> {
+    annotations.iter()
+        .filter(|a| a.from_date.as_str() <= date && date <= a.to_date.as_str())
+        .map(|a| a.label.clone())
+        .collect()
+}
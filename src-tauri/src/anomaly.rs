@@ -0,0 +1,211 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::notifier::Notifier;
+use crate::report::{format_count, format_usd};
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Duration, Local, NaiveDate};
+use serde::{Serialize, Deserialize};
+use tokio_rusqlite::Connection;
+
+// Days before the checked one used as its baseline
+const BASELINE_DAYS: i64 = 28;
+// Apps younger than this have no meaningful baseline yet
+const MIN_BASELINE_DAYS: usize = 14;
+// Modified z-score above which a day is abnormal, 3.5 is the usual cut-off
+const THRESHOLD: f64 = 3.5;
+// Refund rates of days with fewer units sold are too noisy to be compared
+const MIN_REFUND_SAMPLE: i64 = 10;
+// Unit changes smaller than this are never reported, small apps swing a lot in relative terms
+const MIN_UNITS_CHANGE: f64 = 5.0;
+// Days checked after each sync, older ones were already notified
+const RECENT_DAYS: i64 = 3;
+// Steam keeps adding sales to a day until it closes, two days later
+const CLOSING_DELAY_DAYS: i64 = 2;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyMetric {
+    RefundRate,
+    Units,
+    Revenue,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnomalyDirection {
+    Spike,
+    Drop,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Anomaly {
+    pub date: String,
+    pub appid: String,
+    pub app_name: Option<String>,
+    pub metric: AnomalyMetric,
+    pub direction: AnomalyDirection,
+    pub value: f64,
+    // Median of the baseline days
+    pub baseline: f64,
+    // Modified z-score, how many robust deviations away from the baseline
+    pub score: f64,
+}
+
+
+// Abnormal days per app between `from` and `to`, each day is compared to the days before it
+pub async fn get_anomalies(connection: &Connection, from: NaiveDate, to: NaiveDate) -> Result<Vec<Anomaly>, ErrorType> {
+    let baseline_from = from - Duration::days(BASELINE_DAYS);
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(baseline_from)), Some(app::format_date(to))).await?;
+    Ok(detect(&rows, from, to))
+}
+
+
+// Notifies the anomalies of the last closed days that were not notified yet
+pub async fn check_recent(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
+    let last_closed = Local::now().date_naive() - Duration::days(CLOSING_DELAY_DAYS);
+    let anomalies = get_anomalies(connection, last_closed - Duration::days(RECENT_DAYS - 1), last_closed).await?;
+
+    for anomaly in anomalies.into_iter() {
+        if !database::insert_notified_anomaly(connection, &anomaly).await? {
+            continue;
+        }
+        let (title, body) = describe(&anomaly);
+        log::info!("Anomaly detected: {} - {}", title, body);
+        notifier.notify(&title, &body);
+    }
+
+    Ok(())
+}
+
+
+pub fn detect(rows: &[CPartnerFinancialsDetailedSalesResult], from: NaiveDate, to: NaiveDate) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+
+    for (appid, days) in summary::daily_by_group(rows, GroupBy::App).iter() {
+        let first_sale = match days.keys().next().map(|d| app::parse_date(d)) {
+            Some(Ok(first_sale)) => first_sale,
+            _ => continue,
+        };
+        let app_name = days.values().find_map(|d| d.label.clone());
+
+        let mut date = from;
+        while date <= to {
+            let baseline_from = (date - Duration::days(BASELINE_DAYS)).max(first_sale);
+            let baseline: Vec<Option<&SummaryRow>> = iter_days(baseline_from, date - Duration::days(1))
+                .map(|d| days.get(&app::format_date(d)))
+                .collect();
+            if baseline.len() >= MIN_BASELINE_DAYS {
+                let current = days.get(&app::format_date(date));
+                for metric in [AnomalyMetric::RefundRate, AnomalyMetric::Units, AnomalyMetric::Revenue] {
+                    if let Some(anomaly) = check(metric, current, &baseline) {
+                        anomalies.push(Anomaly {
+                            date: app::format_date(date),
+                            appid: appid.clone(),
+                            app_name: app_name.clone(),
+                            ..anomaly
+                        });
+                    }
+                }
+            }
+            date += Duration::days(1);
+        }
+    }
+
+    anomalies.sort_by(|a, b| a.date.cmp(&b.date).then(b.score.abs().total_cmp(&a.score.abs())));
+    anomalies
+}
+
+
+fn iter_days(from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+    from.iter_days().take_while(move |d| *d <= to)
+}
+
+
+// Days without sales count as zero, except for the refund rate where they have no rate at all
+fn metric_value(metric: AnomalyMetric, day: Option<&SummaryRow>) -> Option<f64> {
+    match metric {
+        AnomalyMetric::RefundRate => day.filter(|d| d.gross_units_sold >= MIN_REFUND_SAMPLE).map(|d| d.refund_rate()),
+        AnomalyMetric::Units => Some(day.map(|d| d.net_units_sold as f64).unwrap_or(0.0)),
+        AnomalyMetric::Revenue => Some(day.map(|d| d.net_sales_usd).unwrap_or(0.0)),
+    }
+}
+
+
+fn check(metric: AnomalyMetric, current: Option<&SummaryRow>, baseline: &[Option<&SummaryRow>]) -> Option<Anomaly> {
+    let value = metric_value(metric, current)?;
+    let history: Vec<f64> = baseline.iter().filter_map(|d| metric_value(metric, *d)).collect();
+    if history.len() < MIN_BASELINE_DAYS {
+        return None;
+    }
+
+    let score = modified_z_score(value, &history)?;
+    let baseline = median(&history);
+    if score.abs() < THRESHOLD || (metric == AnomalyMetric::Units && (value - baseline).abs() < MIN_UNITS_CHANGE) {
+        return None;
+    }
+    // Less refunds than usual is good news
+    if metric == AnomalyMetric::RefundRate && score < 0.0 {
+        return None;
+    }
+
+    Some(Anomaly {
+        date: String::new(),
+        appid: String::new(),
+        app_name: None,
+        metric,
+        direction: if score > 0.0 { AnomalyDirection::Spike } else { AnomalyDirection::Drop },
+        value,
+        baseline,
+        score,
+    })
+}
+
+
+// 0.6745 * (x - median) / MAD, falls back to the mean absolute deviation when more than half
+// of the baseline is the same value, None when the baseline is flat
+fn modified_z_score(value: f64, history: &[f64]) -> Option<f64> {
+    let center = median(history);
+    let deviations: Vec<f64> = history.iter().map(|v| (v - center).abs()).collect();
+    let mad = median(&deviations);
+    if mad > 0.0 {
+        return Some(0.6745 * (value - center) / mad);
+    }
+    let mean_deviation = deviations.iter().sum::<f64>() / deviations.len() as f64;
+    if mean_deviation > 0.0 {
+        return Some((value - center) / (1.253314 * mean_deviation));
+    }
+    None
+}
+
+
+fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut values = values.to_vec();
+    values.sort_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+
+fn describe(anomaly: &Anomaly) -> (String, String) {
+    let app = anomaly.app_name.clone().unwrap_or(anomaly.appid.clone());
+    let (what, value, baseline) = match anomaly.metric {
+        AnomalyMetric::RefundRate => ("refund rate", format!("{:.1}%", anomaly.value * 100.0), format!("{:.1}%", anomaly.baseline * 100.0)),
+        AnomalyMetric::Units => ("units", format_count(anomaly.value as i64), format_count(anomaly.baseline as i64)),
+        AnomalyMetric::Revenue => ("revenue", format_usd(anomaly.value), format_usd(anomaly.baseline)),
+    };
+    let title = match (anomaly.metric, anomaly.direction) {
+        (AnomalyMetric::RefundRate, _) => format!("Refund spike on {}", app),
+        (_, AnomalyDirection::Spike) => format!("Unusual {} spike on {}", what, app),
+        (_, AnomalyDirection::Drop) => format!("Unusual {} drop on {}", what, app),
+    };
+    (title, format!("{}: {} {} instead of {} usually", anomaly.date, what, value, baseline))
+}
@@ -0,0 +1,248 @@
+use crate::app::{self, ErrorType, SETTINGS};
+use crate::bundle;
+use crate::database;
+use crate::metrics;
+use crate::summary::{self, GroupBy, SummaryResponse};
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use once_cell::sync::Lazy;
+use serde::{Serialize, Deserialize};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_rusqlite::Connection;
+
+
+pub const DEFAULT_PORT: u16 = 8797;
+
+// Opt-in read only HTTP API for BI tools, only reachable from this machine
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    // Sent as `Authorization: Bearer <token>` or `?token=<token>`, generated when empty
+    pub token: String,
+    // Also serve `/metrics` for Prometheus, with the same token
+    pub metrics: bool,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        ApiSettings { enabled: false, port: DEFAULT_PORT, token: String::new(), metrics: false }
+    }
+}
+
+#[derive(Clone)]
+struct ApiState {
+    connection: Connection,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct SalesQuery {
+    from: Option<String>,
+    to: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SummaryQuery {
+    from: Option<String>,
+    to: Option<String>,
+    format: Option<String>,
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    api_key_set: bool,
+    highwatermark: Option<String>,
+    sales_rows: usize,
+    first_date: Option<String>,
+    last_date: Option<String>,
+}
+
+static SERVER: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
+
+
+// Stops the running server if any, then starts it again when enabled
+pub async fn restart(connection: Connection, settings: ApiSettings) -> Result<(), ErrorType> {
+    let mut server = SERVER.lock().await;
+    if let Some(handle) = server.take() {
+        handle.abort();
+        log::info!("API server stopped");
+    }
+
+    if !settings.enabled {
+        return Ok(());
+    }
+    if settings.token.is_empty() {
+        return Err(ErrorType::Forbidden("The API can't be started without a token".into()));
+    }
+
+    let address = format!("127.0.0.1:{}", settings.port);
+    let listener = tokio::net::TcpListener::bind(&address).await
+        .map_err(|e| ErrorType::BadHttpRequest(format!("Could not listen on {}: {}", address, e)))?;
+    let router = router(ApiState { connection, token: settings.token }, settings.metrics);
+
+    log::info!("API server listening on http://{}", address);
+    *server = Some(tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            log::error!("API server failed: {}", e);
+        }
+    }));
+
+    Ok(())
+}
+
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+fn router(state: ApiState, metrics: bool) -> Router {
+    let mut router = Router::new()
+        .route("/api/sales", get(get_sales))
+        .route("/api/summary", get(get_summary))
+        .route("/api/status", get(get_status))
+        .route("/api/annotations", get(get_annotations));
+    if metrics {
+        router = router.route("/metrics", get(get_metrics));
+    }
+    router
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state)
+}
+
+
+async fn authenticate(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let header_token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(String::from);
+    let query_token = request.uri().query()
+        .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("token=")))
+        .map(String::from);
+
+    match header_token.or(query_token) {
+        Some(token) if tokens_match(&token, &state.token) => next.run(request).await,
+        _ => error_response(ErrorType::BadToken("Missing or invalid API token".into())),
+    }
+}
+
+
+// Compares every byte so the time taken doesn't tell how much of the token was right
+fn tokens_match(token: &str, expected: &str) -> bool {
+    if token.len() != expected.len() {
+        return false;
+    }
+    token.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+
+async fn get_sales(State(state): State<ApiState>, Query(query): Query<SalesQuery>) -> Response {
+    let result = async {
+        let (from, to) = parse_range(query.from, query.to)?;
+        let rows = database::get_sale_details_by_date(&state.connection, from, to).await?;
+        respond(&rows, query.format.as_deref())
+    }.await;
+    result.unwrap_or_else(error_response)
+}
+
+
+async fn get_summary(State(state): State<ApiState>, Query(query): Query<SummaryQuery>) -> Response {
+    let result = async {
+        let (from, to) = parse_range(query.from, query.to)?;
+        let rows = database::get_sale_details_by_date(&state.connection, from.clone(), to.clone()).await?;
+        let bundles = bundle::load(&state.connection).await?;
+        let summaries = summary::summarize_with_bundles(&rows, query.group_by.unwrap_or(GroupBy::Date), &bundles);
+        match query.format.as_deref().unwrap_or("json") {
+            "json" => {
+                let annotations = database::get_annotations(&state.connection, from, to).await?;
+                Ok(Json(SummaryResponse { rows: summaries, total: summary::total(&rows), annotations }).into_response())
+            }
+            // A CSV is a single table, its last line is the total and the events are in /api/annotations
+            format => respond(&[summaries, vec![summary::total(&rows)]].concat(), Some(format)),
+        }
+    }.await;
+    result.unwrap_or_else(error_response)
+}
+
+
+async fn get_annotations(State(state): State<ApiState>, Query(query): Query<SalesQuery>) -> Response {
+    let result = async {
+        let (from, to) = parse_range(query.from, query.to)?;
+        let annotations = database::get_annotations(&state.connection, from, to).await?;
+        respond(&annotations, query.format.as_deref())
+    }.await;
+    result.unwrap_or_else(error_response)
+}
+
+
+async fn get_status(State(state): State<ApiState>) -> Response {
+    let result = async {
+        let (first_date, last_date) = database::get_sales_date_range(&state.connection).await?;
+        let sales_rows = database::count_sale_details(&state.connection, None, None).await?;
+        let settings = SETTINGS.read().await;
+        let status = Status {
+            api_key_set: settings.steam_api_key.as_ref().is_some_and(|key| !key.is_empty()),
+            highwatermark: settings.highwatermark.clone(),
+            sales_rows,
+            first_date,
+            last_date,
+        };
+        drop(settings);
+        Ok::<_, ErrorType>(Json(status).into_response())
+    }.await;
+    result.unwrap_or_else(error_response)
+}
+
+
+async fn get_metrics(State(state): State<ApiState>) -> Response {
+    match metrics::render(&state.connection).await {
+        Ok(text) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+
+// Dates are accepted as `yyyy-MM-dd` or `yyyy/MM/dd`, the database uses the latter
+fn parse_range(from: Option<String>, to: Option<String>) -> Result<(Option<String>, Option<String>), ErrorType> {
+    let parse = |date: Option<String>| date.map(|d| app::parse_date(&d).map(app::format_date)).transpose();
+    Ok((parse(from)?, parse(to)?))
+}
+
+
+fn respond<T: Serialize>(rows: &[T], format: Option<&str>) -> Result<Response, ErrorType> {
+    match format.unwrap_or("json") {
+        "json" => Ok(Json(rows).into_response()),
+        "csv" => {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"));
+            Ok((headers, app::write_csv(rows, b',')?).into_response())
+        }
+        format => Err(ErrorType::BadFormatting(format!("Unknown format: {}", format))),
+    }
+}
+
+
+fn error_response(error: ErrorType) -> Response {
+    let status = match error {
+        ErrorType::Missing(_) => StatusCode::NOT_FOUND,
+        ErrorType::BadToken(_) => StatusCode::UNAUTHORIZED,
+        ErrorType::Forbidden(_) => StatusCode::FORBIDDEN,
+        ErrorType::BadFormatting(_) => StatusCode::BAD_REQUEST,
+        // Database errors
+        ErrorType::BadRequest(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorType::BadHttpRequest(_) => StatusCode::BAD_GATEWAY,
+    };
+    (status, Json(app::format_error_for_webview(&error))).into_response()
+}
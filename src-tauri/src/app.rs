@@ -1,11 +1,20 @@
+use crate::alert;
+use crate::anomaly;
+use crate::api;
 use crate::database;
+use crate::digest;
+use crate::metrics;
+use crate::notifier::Notifier;
+use crate::pricing;
+use crate::schedule;
 use crate::steam;
+use crate::summary;
+use crate::target;
+use crate::webhook::{self, WebhookEvent, WebhookMessage};
 use std::fmt;
 use std::io::Write;
 use aes_gcm::aead::rand_core::RngCore;
-use tauri::{AppHandle, Emitter};
 use futures::stream::{self, StreamExt};
-use tauri_plugin_notification::NotificationExt;
 use std::time::{ Instant, Duration };
 use tokio_rusqlite::Connection;
 use tokio::task;
@@ -23,6 +32,7 @@ use argon2::password_hash::SaltString;
 use base64::{engine::general_purpose, Engine as _};
 use keyring;
 use directories::{ProjectDirs};
+use chrono::NaiveDate;
 
 
 
@@ -49,6 +59,19 @@ impl fmt::Display for ErrorType {
     }
 }
 
+impl ErrorType {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ErrorType::Missing(_) => "Missing",
+            ErrorType::BadHttpRequest(_) => "BadHttpRequest",
+            ErrorType::BadToken(_) => "BadToken",
+            ErrorType::BadRequest(_) => "BadRequest",
+            ErrorType::BadFormatting(_) => "BadFormatting",
+            ErrorType::Forbidden(_) => "Forbidden",
+        }
+    }
+}
+
 // Helper to format error in compatible JS Error format
 pub fn format_error_for_webview(error: &ErrorType) -> serde_json::Value {
     match error {
@@ -79,7 +102,7 @@ pub static SETTINGS: Lazy<Arc<RwLock<Settings>>> = Lazy::new(|| {
     }))
 });
 
-pub async fn set_settings(connection: &Connection, app_handle: &AppHandle) -> Result<(), ErrorType> {
+pub async fn set_settings(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
     let sets = database::get_settings(&connection).await;
 
     match sets {
@@ -90,11 +113,11 @@ pub async fn set_settings(connection: &Connection, app_handle: &AppHandle) -> Re
             settings.poll_interval = sets.poll_interval;
             settings.highwatermark = sets.highwatermark;
             // steam::check_api_key(settings.steam_api_key.clone()).await?;
-            let _ = app_handle.emit("settings-updated", settings.clone());
+            notifier.emit("settings-updated", json!(settings.clone()));
         },
         Err(e) => {
             if let ErrorType::BadToken(_) = e {
-                app_handle.emit("decryption-failed", format_error_for_webview(&e)).unwrap();
+                notifier.emit("decryption-failed", format_error_for_webview(&e));
             }
             return Err(e);
         }
@@ -112,13 +135,13 @@ pub async fn set_settings(connection: &Connection, app_handle: &AppHandle) -> Re
     Ok(())
 }
 
-pub async fn create_password(app_handle: &AppHandle) -> Result<(), ErrorType> {
+pub async fn create_password(notifier: &dyn Notifier) -> Result<(), ErrorType> {
     let _password = match get_password().await {
         Ok(p) => p,
         Err(e) => {
             // Check if the error is ErrorType::Forbidden (indicating user denied access)
             if let ErrorType::Forbidden(_) = e {
-                app_handle.emit("denied-keyring-access", format_error_for_webview(&e)).unwrap();
+                notifier.emit("denied-keyring-access", format_error_for_webview(&e));
                 return Err(e);
             } else {
                 // Create a default password if not found
@@ -137,10 +160,10 @@ pub async fn create_password(app_handle: &AppHandle) -> Result<(), ErrorType> {
 }
 
 
-pub async fn start(app_handle: AppHandle) -> Result<String, ErrorType> {
-    log::info!("Starting Steamboard - v{}", app_handle.package_info().version);
+pub async fn start(notifier: Arc<dyn Notifier>, version: String) -> Result<String, ErrorType> {
+    log::info!("Starting Steamboard - v{}", version);
 
-    let connection = database::create(app_handle.clone()).await?;
+    let connection = database::create().await?;
 
     let has_settings = database::has_settings(&connection).await?;
     if has_settings {
@@ -151,10 +174,15 @@ pub async fn start(app_handle: AppHandle) -> Result<String, ErrorType> {
         }
         drop(settings);
 
-        create_password(&app_handle).await?;
-        set_settings(&connection, &app_handle).await?;
+        create_password(notifier.as_ref()).await?;
+        set_settings(&connection, notifier.as_ref()).await?;
+
+        let api_settings = database::get_api_settings(&connection).await?;
+        if let Err(e) = api::restart(connection.clone(), api_settings).await {
+            log::error!("API server could not start: {}", e);
+        }
         tokio::spawn(async move {
-            if let Err(e) = periodic_sync(&connection, &app_handle).await {
+            if let Err(e) = periodic_sync(&connection, notifier.as_ref()).await {
                 log::error!("Periodic sync failed: {}", e);
             }
         });
@@ -164,21 +192,43 @@ pub async fn start(app_handle: AppHandle) -> Result<String, ErrorType> {
 }
 
 
-pub async fn periodic_sync(connection: &Connection, app_handle: &AppHandle)-> Result<(), ErrorType> {
+pub async fn periodic_sync(connection: &Connection, notifier: &dyn Notifier)-> Result<(), ErrorType> {
     loop {
         let settings = SETTINGS.read().await;
         let poll_interval = settings.poll_interval.unwrap_or(600);
         log::info!("Starting periodic sync with interval: {} seconds", poll_interval);
         drop(settings);
-        sync(&connection, &app_handle).await?;
+        sync(&connection, notifier).await?;
+        // Exports run after the sync so they include the freshest data
+        if let Err(e) = schedule::run_due_schedules(connection, notifier).await {
+            log::error!("Scheduled exports failed: {}", e);
+        }
+        if let Err(e) = digest::send_due_digest(connection).await {
+            log::error!("Email digest failed: {}", e);
+        }
         sleep(Duration::from_secs(poll_interval as u64)).await;
     }
 }
 
 
-pub async fn sync(connection: &Connection, app_handle: &AppHandle) -> Result<(), ErrorType> {
+pub async fn sync(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
+    let result = sync_sales(connection, notifier).await;
+    if let Err(e) = &result {
+        metrics::record_error(e);
+        webhook::dispatch(connection, WebhookMessage {
+            event: WebhookEvent::SyncFailed,
+            title: "Sync failed".into(),
+            body: e.to_string(),
+            data: json!({ "type": e.kind(), "message": e.to_string() }),
+        }).await;
+    }
+    result
+}
+
+
+async fn sync_sales(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
     log::info!("Starting sync...");
-    app_handle.emit("sync-progress", 0).unwrap();
+    notifier.emit("sync-progress", json!(0));
 
     let started_at = Instant::now();
 
@@ -189,9 +239,9 @@ pub async fn sync(connection: &Connection, app_handle: &AppHandle) -> Result<(),
 
     // If no API key is set, try to reload settings with initialization
     if steam_api_key.is_none() || steam_api_key.as_ref().unwrap().is_empty() {
-        app_handle.emit("sync-progress", 1).unwrap();
+        notifier.emit("sync-progress", json!(1));
 
-        let _ = create_password(&app_handle).await;
+        let _ = create_password(notifier).await;
         let settings = SETTINGS.read().await;
         steam_api_key = settings.steam_api_key.clone();
         highwatermark = settings.highwatermark.clone();
@@ -212,11 +262,11 @@ pub async fn sync(connection: &Connection, app_handle: &AppHandle) -> Result<(),
     let dates_for_stream = dates.clone();
     let dates_len = dates_for_stream.len();
 
-    let sales_details: Vec<Vec<steam::CPartnerFinancialsDetailedSalesResult>> = stream::iter(dates_for_stream.into_iter().enumerate())
+    let sales_details: Vec<(Vec<steam::CPartnerFinancialsDetailedSalesResult>, steam::SchemaDrift)> = stream::iter(dates_for_stream.into_iter().enumerate())
         .map(|(i, date)| {
             let steam_api_key = steam_api_key.clone();
             let conn = connection.clone();
-            app_handle.emit("sync-progress", (i as f32) / (dates_len as f32)).unwrap();
+            notifier.emit("sync-progress", json!((i as f32) / (dates_len as f32)));
             async move {
                 sync_one_date(conn, steam_api_key, date).await
             }
@@ -225,11 +275,12 @@ pub async fn sync(connection: &Connection, app_handle: &AppHandle) -> Result<(),
         .filter_map(|res| async {
             match res {
                 Ok(r) => {
-                    let _ = app_handle.emit("sync-data", &r);
+                    notifier.emit("sync-data", json!(&r.0));
                     Some(r)
                 }
                 Err(e) => {
                     log::error!("Error syncing date: {}", e);
+                    metrics::record_error(&e);
                     None
                 }
             }
@@ -238,68 +289,102 @@ pub async fn sync(connection: &Connection, app_handle: &AppHandle) -> Result<(),
         .await;
 
     // Flatten the Vec<Vec<_>> into Vec<_>
-    let all_sales_details: Vec<steam::CPartnerFinancialsDetailedSalesResult> = sales_details.into_iter().flatten().collect();
+    let mut schema_drift = steam::SchemaDrift::default();
+    let all_sales_details: Vec<steam::CPartnerFinancialsDetailedSalesResult> = sales_details.into_iter()
+        .flat_map(|(details, drift)| {
+            schema_drift.merge(drift);
+            details
+        })
+        .collect();
+
+    if !schema_drift.is_empty() {
+        log::warn!(
+            "Steam response schema changed: unknown fields {:?}, {} rows could not be parsed",
+            schema_drift.unknown_fields,
+            schema_drift.parse_failures
+        );
+        notifier.emit("schema-drift", json!(&schema_drift));
+    }
 
     let mut settings = SETTINGS.write().await;
-    settings.highwatermark = Some(changed_dates.result_highwatermark);
+    settings.highwatermark = Some(changed_dates.result_highwatermark.clone());
     drop(settings);
 
-    let _ = database::save_settings(&connection).await?;
+    // Only the highwatermark changed, the API key doesn't need the keyring to be encrypted again
+    database::save_highwatermark(connection, changed_dates.result_highwatermark).await?;
 
-    app_handle.emit("sync-progress", 1).unwrap();
+    notifier.emit("sync-progress", json!(1));
     log::info!("Sync done in {:?}, {} sales details added", started_at.elapsed(), all_sales_details.len());
+    metrics::record_sync(started_at.elapsed(), all_sales_details.len());
 
     if first_sync {
         log::info!("Sending notification: Initial sync completed successfully!");
-        app_handle.notification()
-            .builder()
-            .title("Sync completed")
-            .body("Initial sync completed successfully!")
-            .show()
-            .unwrap();
+        notifier.notify("Sync completed", "Initial sync completed successfully!");
     } else {
         // Notify user about new sales
-        let sales_amount = all_sales_details.len();
-        let summary = if sales_amount == 1 {
-            format!("{} new sale", sales_amount)
+        let totals = summary::total(&all_sales_details);
+        let units = totals.net_units_sold;
+        let title = if units == 1 {
+            format!("{} new sale", units)
         } else {
-            format!("{} new sales", sales_amount)
+            format!("{} new sales", units)
         };
 
-        let body = if sales_amount < 10 {
-            format!("Way to go!")
-        } else if sales_amount < 50 {
-            format!("Well done!")
-        } else if sales_amount < 50 {
-            format!("Wow, that's a lot!")
-        } else if sales_amount < 100 {
-            format!("You are a sale machine!")
-        } else if sales_amount < 200 {
-            format!("You're on fire!")
-        } else if sales_amount < 500 {
-            format!("Ka-ching! You are a sale monster!")
-        } else {
-            format!("Jackpot!")
-        };
+        // Thresholds and milestones are left to the alert rules
+        let body = format!("${:.2} net revenue", totals.net_sales_usd);
+
+        if units > 0 {
+            log::info!("Sending notification: {} - {}", title, body);
+            notifier.notify(&title, &body);
+        }
 
-        if sales_amount > 0 {
-            log::info!("Sending notification: {} - {}", summary, body);
-            app_handle.notification()
-                .builder()
-                .title(&summary)
-                .body(&body)
-                .show()
-                .unwrap();
+        if !all_sales_details.is_empty() {
+            webhook::dispatch(connection, WebhookMessage {
+                event: WebhookEvent::NewSales,
+                title,
+                body: format!("{} units, ${:.2} net revenue", totals.net_units_sold, totals.net_sales_usd),
+                data: json!({
+                    "rows": all_sales_details.len(),
+                    "net_units_sold": totals.net_units_sold,
+                    "gross_sales_usd": totals.gross_sales_usd,
+                    "net_sales_usd": totals.net_sales_usd,
+                }),
+            }).await;
         }
     }
 
+    if let Err(e) = pricing::update_history(connection, dates.iter().min().cloned()).await {
+        log::error!("Price history failed: {}", e);
+    }
+    if let Err(e) = alert::evaluate_rules(connection, notifier).await {
+        log::error!("Alert rules failed: {}", e);
+    }
+    if let Err(e) = anomaly::check_recent(connection, notifier).await {
+        log::error!("Anomaly detection failed: {}", e);
+    }
+    if let Err(e) = target::check_targets(connection, notifier).await {
+        log::error!("Sales targets failed: {}", e);
+    }
+
+    webhook::dispatch(connection, WebhookMessage {
+        event: WebhookEvent::SyncCompleted,
+        title: "Sync completed".into(),
+        body: format!("{} sales details added", all_sales_details.len()),
+        data: json!({
+            "rows": all_sales_details.len(),
+            "duration_seconds": started_at.elapsed().as_secs_f64(),
+            "first_sync": first_sync,
+        }),
+    }).await;
+
     Ok(())
 }
 
 
-async fn sync_one_date(conn: Connection, api_key: Option<String>, date: String) -> Result<Vec<steam::CPartnerFinancialsDetailedSalesResult>, ErrorType> {
+async fn sync_one_date(conn: Connection, api_key: Option<String>, date: String) -> Result<(Vec<steam::CPartnerFinancialsDetailedSalesResult>, steam::SchemaDrift), ErrorType> {
     let mut highwatermark = database::get_highwatermark(&conn, date.to_string()).await?;
     let mut incoming_sales_details = Vec::new();
+    let mut schema_drift = steam::SchemaDrift::default();
     log::info!("Syncing {} (watermark: {})...", date, highwatermark);
     if highwatermark == 0 {
         log::info!("Deleting if existing sale details for {}", date);
@@ -312,13 +397,14 @@ async fn sync_one_date(conn: Connection, api_key: Option<String>, date: String)
             break;
         }
         highwatermark = max_id;
+        schema_drift.record(&date_detailed_sales);
         let mut inserted_sales_details = database::insert_sale_detail(&conn, date_detailed_sales).await?;
         log::info!("Inserted {} sales details for {}", inserted_sales_details.len(), date);
         incoming_sales_details.append(&mut inserted_sales_details);
     }
     database::insert_sale_date(&conn, date.to_string(), highwatermark.to_string()).await?;
 
-    Ok(incoming_sales_details)
+    Ok((incoming_sales_details, schema_drift))
 }
 
 
@@ -342,7 +428,7 @@ pub fn write_csv<T>(data: &[T], delimiter: u8) -> Result<String, ErrorType> wher
 }
 
 
-pub async fn export_to_csv(data: Vec<steam::CPartnerFinancialsDetailedSalesResult>, path: String, delimiter: u8) -> Result<String, ErrorType> {
+pub async fn export_to_csv<T>(data: Vec<T>, path: String, delimiter: u8) -> Result<String, ErrorType> where T: serde::Serialize + Send + 'static, {
     let path_clone = path.clone();
     let _ = task::spawn_blocking(move || -> Result<(), ErrorType> {
         let mut file = std::fs::File::create(Path::new(&path_clone)).map_err(|e| ErrorType::BadFormatting(format!("File error: {}", e)))?;
@@ -457,7 +543,25 @@ pub async fn set_password(password: &str) -> Result<(), ErrorType> {
     .map_err(|e| ErrorType::Forbidden(format!("Failed to set password in keyring: {}", e)))?
 }
 
+// Steam and the webview both use `yyyy/MM/dd` dates
+pub const DATE_FORMAT: &str = "%Y/%m/%d";
+
+pub fn format_date(date: NaiveDate) -> String {
+    date.format(DATE_FORMAT).to_string()
+}
+
+pub fn parse_date(date: &str) -> Result<NaiveDate, ErrorType> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT)
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d"))
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid date {}: {}", date, e)))
+}
+
 pub fn get_data_local_dir() -> Result<std::path::PathBuf, ErrorType> {
+    // Servers keep the database wherever they want, like the systemd state directory
+    if let Ok(data_dir) = std::env::var("STEAMBOARD_DATA_DIR") {
+        return Ok(std::path::PathBuf::from(data_dir));
+    }
+
     let Some(steamboard_dirs) = ProjectDirs::from("com", "fatfishlab", "steamboard") else {
         return Err(ErrorType::Missing("Could not find local data directory".to_string()));
     };
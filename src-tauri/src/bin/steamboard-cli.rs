@@ -0,0 +1,287 @@
+// Command line access to the Steamboard database, for build servers and scripts.
+// Uses the same database and keyring entry as the desktop app.
+// Without a keyring, the digest SMTP password comes from STEAMBOARD_SMTP_PASSWORD.
+use clap::{Parser, Subcommand};
+use serde::de::DeserializeOwned;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_rusqlite::Connection;
+
+use tauri_app_lib::api;
+use tauri_app_lib::app::{self, ErrorType, SETTINGS};
+use tauri_app_lib::bundle;
+use tauri_app_lib::clipboard::{self, ClipboardFormat};
+use tauri_app_lib::database;
+use tauri_app_lib::digest;
+use tauri_app_lib::export::{self, Compression, ExportFormat, ExportOptions};
+use tauri_app_lib::notifier::LogNotifier;
+use tauri_app_lib::schedule;
+use tauri_app_lib::summary::GroupBy;
+use tauri_app_lib::webhook;
+
+
+#[derive(Parser)]
+#[command(name = "steamboard-cli", version, about = "Steamboard, own your Steam sales data")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// File holding the Steam API key, used instead of the keyring. STEAMBOARD_API_KEY works too
+    #[arg(long, global = true, env = "STEAMBOARD_API_KEY_FILE")]
+    api_key_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch the new sales from Steam
+    Sync,
+    /// Export the sales to a file
+    Export {
+        /// csv, json, ndjson, parquet, arrow, xlsx or ods
+        #[arg(long, default_value = "csv", value_parser = parse_enum::<ExportFormat>)]
+        format: ExportFormat,
+        #[arg(long, short)]
+        output: String,
+        /// First day, yyyy/MM/dd or yyyy-MM-dd
+        #[arg(long, value_parser = parse_date)]
+        from: Option<String>,
+        /// Last day, yyyy/MM/dd or yyyy-MM-dd
+        #[arg(long, value_parser = parse_date)]
+        to: Option<String>,
+        /// Id of a saved export profile, CSV only
+        #[arg(long)]
+        profile: Option<i64>,
+        #[arg(long, default_value = ",")]
+        delimiter: String,
+        /// none, gzip or zip
+        #[arg(long, default_value = "none", value_parser = parse_enum::<Compression>)]
+        compression: Compression,
+    },
+    /// Print the sales totals grouped by date, app, package, country or discount
+    Summary {
+        #[arg(long, default_value = "app", value_parser = parse_enum::<GroupBy>)]
+        group_by: GroupBy,
+        #[arg(long, value_parser = parse_date)]
+        from: Option<String>,
+        #[arg(long, value_parser = parse_date)]
+        to: Option<String>,
+        /// csv, tsv, markdown or html
+        #[arg(long, default_value = "markdown", value_parser = parse_enum::<ClipboardFormat>)]
+        format: ClipboardFormat,
+    },
+    /// Show what is in the database
+    Status,
+    /// Keep syncing until stopped, for servers without a display. Logs go to stdout
+    #[command(alias = "headless")]
+    Daemon {
+        /// Seconds between two syncs, the desktop app setting is used when not set
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+}
+
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    // The daemon output is meant for journald, the other commands keep stdout for their results
+    let target = match cli.command {
+        Command::Daemon { .. } => env_logger::Target::Stdout,
+        _ => env_logger::Target::Stderr,
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .target(target)
+        .init();
+
+    if let Err(e) = run(cli.command, cli.api_key_file).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+
+async fn run(command: Command, api_key_file: Option<PathBuf>) -> Result<(), ErrorType> {
+    let steamboard_dir = app::get_data_local_dir()
+        .map_err(|e| ErrorType::Missing(format!("Failed to generate local data path: {}", e)))?;
+    std::fs::create_dir_all(&steamboard_dir)
+        .map_err(|e| ErrorType::Missing(format!("Failed to create steamboard local data directory: {}", e)))?;
+
+    let connection = database::create().await?;
+
+    match command {
+        Command::Sync => {
+            load_settings(&connection, api_key_file).await?;
+            let result = app::sync(&connection, &LogNotifier).await;
+            // The webhooks are sent in the background, they'd be dropped with the runtime
+            webhook::wait_for_deliveries().await;
+            result?;
+            println!("Sync completed");
+        }
+        Command::Daemon { interval } => {
+            load_settings(&connection, api_key_file).await?;
+            let api_settings = database::get_api_settings(&connection).await?;
+            // The daemon keeps syncing without the API
+            if let Err(e) = api::restart(connection.clone(), api_settings).await {
+                log::error!("Failed to start the API server: {}", e);
+            }
+            daemon(&connection, interval).await;
+        }
+        Command::Export { format, output, from, to, profile, delimiter, compression } => {
+            let profile = match profile {
+                Some(id) => Some(database::get_export_profile(&connection, id).await?),
+                None => None,
+            };
+            let options = ExportOptions {
+                format,
+                delimiter: delimiter.bytes().next().unwrap_or(b','),
+                profile,
+                compression,
+            };
+            let result = export::export(&connection, from, to, output, options, |progress| {
+                log::debug!("Export progress: {:.0}%", progress * 100.0);
+            }).await?;
+            println!("{}", result);
+        }
+        Command::Summary { group_by, from, to, format } => {
+            let rows = database::get_sale_details_by_date(&connection, from.clone(), to.clone()).await?;
+            let bundles = bundle::load(&connection).await?;
+            let annotations = database::get_annotations(&connection, from, to).await?;
+            let content = clipboard::copy_summary(&rows, group_by, &bundles, &annotations, format, b',')?;
+            let text = content.html.unwrap_or(content.text);
+            let _ = std::io::stdout().write_all(text.as_bytes());
+        }
+        Command::Status => {
+            let has_settings = database::has_settings(&connection).await?;
+            let sales = database::count_sale_details(&connection, None, None).await?;
+            let (first_date, last_date) = database::get_sales_date_range(&connection).await?;
+
+            println!("Data directory: {}", steamboard_dir.display());
+            println!("API key:        {}", if has_settings { "set" } else { "missing" });
+            if has_settings && app::set_settings(&connection, &LogNotifier).await.is_ok() {
+                let settings = SETTINGS.read().await;
+                println!("Highwatermark:  {}", settings.highwatermark.clone().unwrap_or("0".into()));
+            }
+            println!("Sales rows:     {}", sales);
+            println!("First day:      {}", first_date.unwrap_or("-".into()));
+            println!("Last day:       {}", last_date.unwrap_or("-".into()));
+        }
+    }
+
+    Ok(())
+}
+
+
+// The API key comes from STEAMBOARD_API_KEY or a file when there is no keyring to decrypt it with
+async fn load_settings(connection: &Connection, api_key_file: Option<PathBuf>) -> Result<(), ErrorType> {
+    let api_key = match (std::env::var("STEAMBOARD_API_KEY"), api_key_file) {
+        (Ok(api_key), _) => Some(api_key),
+        (Err(_), Some(path)) => Some(
+            std::fs::read_to_string(&path)
+                .map_err(|e| ErrorType::Missing(format!("Failed to read API key file {}: {}", path.display(), e)))?
+        ),
+        (Err(_), None) => None,
+    };
+
+    match api_key {
+        Some(api_key) => {
+            let stored = database::get_encrypted_settings(connection).await?;
+            let mut settings = SETTINGS.write().await;
+            settings.id = stored.id;
+            settings.steam_api_key = Some(api_key.trim().to_string());
+            settings.poll_interval = stored.poll_interval;
+            settings.highwatermark = stored.highwatermark;
+            Ok(())
+        }
+        None => {
+            if !database::has_settings(connection).await? {
+                return Err(ErrorType::Missing("No Steam API key, set STEAMBOARD_API_KEY or set it up in the desktop app first".into()));
+            }
+            app::set_settings(connection, &LogNotifier).await
+        }
+    }
+}
+
+
+// Same loop as the desktop app, but a sync in progress is finished before stopping
+async fn daemon(connection: &Connection, interval: Option<u64>) {
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let poll_interval = match interval {
+            Some(interval) => interval,
+            None => SETTINGS.read().await.poll_interval.unwrap_or(600).max(60) as u64,
+        };
+
+        let sync = async {
+            if let Err(e) = app::sync(connection, &LogNotifier).await {
+                log::error!("Sync failed: {}", e);
+            }
+            if let Err(e) = schedule::run_due_schedules(connection, &LogNotifier).await {
+                log::error!("Scheduled exports failed: {}", e);
+            }
+            if let Err(e) = digest::send_due_digest(connection).await {
+                log::error!("Email digest failed: {}", e);
+            }
+        };
+        tokio::pin!(sync);
+
+        tokio::select! {
+            _ = &mut sync => {}
+            _ = &mut shutdown => {
+                log::info!("Stopping once the current sync is done...");
+                sync.await;
+                break;
+            }
+        }
+
+        log::info!("Next sync in {} seconds", poll_interval);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(poll_interval)) => {}
+            _ = &mut shutdown => break,
+        }
+    }
+
+    webhook::wait_for_deliveries().await;
+    log::info!("Steamboard daemon stopped");
+}
+
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(e) => {
+            log::warn!("Could not listen to SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+
+// The lib enums only know serde, values are parsed like the webview would send them
+fn parse_enum<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_lowercase()))
+        .map_err(|_| format!("unknown value: {}", value))
+}
+
+
+fn parse_date(value: &str) -> Result<String, String> {
+    app::parse_date(value)
+        .map(app::format_date)
+        .map_err(|e| e.to_string())
+}
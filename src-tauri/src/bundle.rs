@@ -0,0 +1,169 @@
+use crate::app::ErrorType;
+use crate::database;
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use tokio_rusqlite::Connection;
+
+// Member weights are compared in one currency and country, regional prices differ within USD
+const BASE_PRICE_CURRENCY: &str = "USD";
+const BASE_PRICE_COUNTRY: &str = "US";
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationMethod {
+    Equal,
+    // Latest USD base price of each member package
+    BasePrice,
+    Manual,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BundleMember {
+    pub appid: i64,
+    // Needed to weight by base price
+    pub packageid: Option<i64>,
+    // Manual allocation only, members add up to 100
+    pub percentage: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BundleRule {
+    pub id: Option<i64>,
+    pub bundleid: i64,
+    pub method: AllocationMethod,
+    pub members: Vec<BundleMember>,
+}
+
+// Share of a bundle going to one member, between 0 and 1
+#[derive(Clone, Debug)]
+struct MemberShare {
+    appid: i64,
+    packageid: Option<i64>,
+    app_name: Option<String>,
+    package_name: Option<String>,
+    share: f64,
+}
+
+// Shares of every bundle with a rule, ready to be applied to sales rows
+#[derive(Clone, Debug, Default)]
+pub struct BundleAllocation {
+    bundles: HashMap<i64, Vec<MemberShare>>,
+}
+
+impl BundleAllocation {
+    pub fn is_empty(&self) -> bool {
+        self.bundles.is_empty()
+    }
+
+    // Bundle rows with a rule become one row per member with its share of the revenue. Every member
+    // keeps the units of the bundle since every buyer got a copy of each game
+    pub fn apply(&self, rows: &[CPartnerFinancialsDetailedSalesResult]) -> Vec<CPartnerFinancialsDetailedSalesResult> {
+        let mut allocated = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let Some(members) = row.bundleid.and_then(|id| self.bundles.get(&(id as i64))) else {
+                allocated.push(row.clone());
+                continue;
+            };
+            let share_of = |value: Option<f32>, share: f64| value.map(|v| (v as f64 * share) as f32);
+            for member in members.iter() {
+                let mut member_row = row.clone();
+                member_row.appid = Some(member.appid as i32);
+                member_row.packageid = member.packageid.map(|id| id as i32);
+                member_row.app_name = member.app_name.clone();
+                member_row.package_name = member.package_name.clone();
+                member_row.gross_sales_usd = share_of(row.gross_sales_usd, member.share);
+                member_row.gross_returns_usd = share_of(row.gross_returns_usd, member.share);
+                member_row.net_tax_usd = share_of(row.net_tax_usd, member.share);
+                member_row.net_sales_usd = share_of(row.net_sales_usd, member.share);
+                member_row.avg_sale_price_usd = share_of(row.avg_sale_price_usd, member.share);
+                allocated.push(member_row);
+            }
+        }
+        allocated
+    }
+}
+
+
+pub fn validate(rule: &BundleRule) -> Result<(), ErrorType> {
+    if rule.members.is_empty() {
+        return Err(ErrorType::BadFormatting(format!("Bundle {} has no members", rule.bundleid)));
+    }
+    for (i, member) in rule.members.iter().enumerate() {
+        if rule.members[..i].iter().any(|m| m.appid == member.appid && m.packageid == member.packageid) {
+            return Err(ErrorType::BadFormatting(format!("App {} is twice in bundle {}", member.appid, rule.bundleid)));
+        }
+    }
+    match rule.method {
+        AllocationMethod::Equal => {}
+        AllocationMethod::BasePrice => {
+            if rule.members.iter().any(|m| m.packageid.is_none()) {
+                return Err(ErrorType::BadFormatting("Every member needs a package to be weighted by base price".into()));
+            }
+        }
+        AllocationMethod::Manual => {
+            if rule.members.iter().any(|m| m.percentage.is_none_or(|p| p < 0.0)) {
+                return Err(ErrorType::BadFormatting("Every member needs a percentage".into()));
+            }
+            let total: f64 = rule.members.iter().filter_map(|m| m.percentage).sum();
+            if (total - 100.0).abs() > 0.01 {
+                return Err(ErrorType::BadFormatting(format!("Percentages add up to {}, not 100", total)));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+pub async fn load(connection: &Connection) -> Result<BundleAllocation, ErrorType> {
+    let rules = database::get_bundle_rules(connection).await?;
+    if rules.is_empty() {
+        return Ok(BundleAllocation::default());
+    }
+
+    // The history is sorted by date, the last period is the current price
+    let mut base_prices: HashMap<i64, i64> = HashMap::new();
+    let history = database::get_price_history(connection, None).await?;
+    for period in history.into_iter().filter(|p| p.currency == BASE_PRICE_CURRENCY && p.country_code == BASE_PRICE_COUNTRY) {
+        base_prices.insert(period.packageid, period.base_price);
+    }
+    let (app_names, package_names) = database::get_product_names(connection).await?;
+
+    let mut bundles = HashMap::new();
+    for rule in rules.iter() {
+        let weights = weights(rule, &base_prices);
+        let total: f64 = weights.iter().sum();
+        let members = rule.members.iter().zip(weights).map(|(member, weight)| MemberShare {
+            appid: member.appid,
+            packageid: member.packageid,
+            app_name: app_names.get(&member.appid).cloned(),
+            package_name: member.packageid.and_then(|id| package_names.get(&id).cloned()),
+            share: if total > 0.0 { weight / total } else { 0.0 },
+        }).collect();
+        bundles.insert(rule.bundleid, members);
+    }
+
+    Ok(BundleAllocation { bundles })
+}
+
+
+fn weights(rule: &BundleRule, base_prices: &HashMap<i64, i64>) -> Vec<f64> {
+    let equal = vec![1.0; rule.members.len()];
+    match rule.method {
+        AllocationMethod::Equal => equal,
+        AllocationMethod::Manual => rule.members.iter().map(|m| m.percentage.unwrap_or(0.0)).collect(),
+        AllocationMethod::BasePrice => {
+            let prices: Option<Vec<f64>> = rule.members.iter()
+                .map(|m| m.packageid.and_then(|id| base_prices.get(&id)).map(|p| *p as f64))
+                .collect();
+            match prices {
+                Some(prices) => prices,
+                None => {
+                    log::warn!("Bundle {} has members never sold in {} in {}, splitting it equally", rule.bundleid, BASE_PRICE_CURRENCY, BASE_PRICE_COUNTRY);
+                    equal
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Duration, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tokio_rusqlite::Connection;
+
+// Valve's major sales and Next Fests, replaced by `steam_events.json` in the data directory when there is one
+const BUNDLED_EVENTS: &str = include_str!("../steam_events.json");
+const EVENTS_FILE: &str = "steam_events.json";
+// Days before an event used as its baseline
+const BASELINE_DAYS: i64 = 28;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SteamEventKind {
+    Sale,
+    NextFest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SteamEvent {
+    pub name: String,
+    pub kind: SteamEventKind,
+    pub from_date: String,
+    pub to_date: String,
+}
+
+// Daily averages during the event and during the baseline before it
+#[derive(Serialize, Clone, Debug)]
+pub struct AppImpact {
+    pub appid: String,
+    pub app_name: Option<String>,
+    pub event_units: i64,
+    pub event_sales_usd: f64,
+    pub event_units_per_day: f64,
+    pub event_sales_usd_per_day: f64,
+    // Baseline days once the app sold, days of other events are left out
+    pub baseline_days: i64,
+    pub baseline_units_per_day: f64,
+    pub baseline_sales_usd_per_day: f64,
+    // Relative change of the daily averages, None when the baseline sold nothing
+    pub units_uplift: Option<f64>,
+    pub sales_uplift: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EventImpact {
+    pub event: SteamEvent,
+    pub baseline_from: String,
+    pub baseline_to: String,
+    pub total: AppImpact,
+    pub apps: Vec<AppImpact>,
+}
+
+
+pub fn load_events() -> Result<Vec<SteamEvent>, ErrorType> {
+    let path = app::get_data_local_dir()?.join(EVENTS_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(json) => parse_events(&json).map_err(|e| ErrorType::BadFormatting(format!("{}: {}", path.display(), e))),
+        Err(_) => parse_events(BUNDLED_EVENTS),
+    }
+}
+
+
+// Checks the file and copies it over the bundled calendar, returns the number of events
+pub fn import_events(path: &str) -> Result<usize, ErrorType> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| ErrorType::BadFormatting(format!("Could not read {}: {}", path, e)))?;
+    let events = parse_events(&json)?;
+    let destination = app::get_data_local_dir()?.join(EVENTS_FILE);
+    std::fs::write(&destination, json)
+        .map_err(|e| ErrorType::BadFormatting(format!("Could not write {}: {}", destination.display(), e)))?;
+    Ok(events.len())
+}
+
+
+fn parse_events(json: &str) -> Result<Vec<SteamEvent>, ErrorType> {
+    let events: Vec<SteamEvent> = serde_json::from_str(json)
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid Steam events: {}", e)))?;
+    let mut normalized = Vec::new();
+    for event in events.into_iter() {
+        let from = app::parse_date(&event.from_date)?;
+        let to = app::parse_date(&event.to_date)?;
+        if to < from {
+            return Err(ErrorType::BadFormatting(format!("{} ends before it starts", event.name)));
+        }
+        normalized.push(SteamEvent { from_date: app::format_date(from), to_date: app::format_date(to), ..event });
+    }
+    normalized.sort_by(|a, b| a.from_date.cmp(&b.from_date));
+    Ok(normalized)
+}
+
+
+// Impact of every event overlapping the range, only events with sales during them are reported
+pub async fn get_impacts(connection: &Connection, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<Vec<EventImpact>, ErrorType> {
+    let events: Vec<(NaiveDate, NaiveDate, SteamEvent)> = load_events()?
+        .into_iter()
+        .filter_map(|e| Some((app::parse_date(&e.from_date).ok()?, app::parse_date(&e.to_date).ok()?, e)))
+        .collect();
+    // Sales during an event don't make a good baseline for the next one
+    let event_days: BTreeSet<NaiveDate> = events.iter()
+        .flat_map(|(start, end, _)| start.iter_days().take_while(move |d| d <= end))
+        .collect();
+    let events: Vec<(SteamEvent, NaiveDate, NaiveDate)> = events.into_iter()
+        .filter(|(start, end, _)| from.is_none_or(|from| *end >= from) && to.is_none_or(|to| *start <= to))
+        .map(|(start, end, e)| (e, start, end))
+        .collect();
+    let (Some(first), Some(last)) = (events.iter().map(|e| e.1).min(), events.iter().map(|e| e.2).max()) else {
+        return Ok(Vec::new());
+    };
+
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(first - Duration::days(BASELINE_DAYS))), Some(app::format_date(last))).await?;
+    let groups = summary::daily_by_group(&rows, GroupBy::App);
+    // Running events, or events past the last sync, are measured on the days we have
+    let Some(last_sale) = rows.iter().map(|r| r.date.as_str()).max().and_then(|d| app::parse_date(d).ok()) else {
+        return Ok(Vec::new());
+    };
+
+    let mut impacts = Vec::new();
+    for (event, start, end) in events.into_iter() {
+        if start > last_sale {
+            continue;
+        }
+        let end = end.min(last_sale);
+        let baseline_from = start - Duration::days(BASELINE_DAYS);
+        let baseline_to = start - Duration::days(1);
+
+        let mut apps: Vec<AppImpact> = groups.iter()
+            .filter_map(|(appid, days)| {
+                let event_totals = sum_days(days, start, end);
+                if event_totals.net_units_sold == 0 && event_totals.net_sales_usd == 0.0 {
+                    return None;
+                }
+                let label = days.values().find_map(|d| d.label.clone());
+                let baseline = baseline_dates(baseline_from, baseline_to, first_sale(days), &event_days);
+                Some(impact(appid.clone(), label, &event_totals, &sum_dates(days, &baseline), baseline.len() as i64, start, end))
+            })
+            .collect();
+        if apps.is_empty() {
+            continue;
+        }
+        apps.sort_by(|a, b| b.event_sales_usd.total_cmp(&a.event_sales_usd));
+
+        let baseline = baseline_dates(baseline_from, baseline_to, groups.values().filter_map(first_sale).min(), &event_days);
+        let mut event_total = SummaryRow::default();
+        let mut baseline_total = SummaryRow::default();
+        for days in groups.values() {
+            event_total.merge(&sum_days(days, start, end));
+            baseline_total.merge(&sum_dates(days, &baseline));
+        }
+
+        impacts.push(EventImpact {
+            baseline_from: app::format_date(baseline_from),
+            baseline_to: app::format_date(baseline_to),
+            total: impact("total".into(), Some("Total".into()), &event_total, &baseline_total, baseline.len() as i64, start, end),
+            apps,
+            event,
+        });
+    }
+
+    Ok(impacts)
+}
+
+
+fn sum_days(days: &BTreeMap<String, SummaryRow>, from: NaiveDate, to: NaiveDate) -> SummaryRow {
+    let mut total = SummaryRow::default();
+    for (_, day) in days.range(app::format_date(from)..=app::format_date(to)) {
+        total.merge(day);
+    }
+    total
+}
+
+
+// Days without sales count as zero once the app sold, days before aren't part of its baseline
+fn baseline_dates(from: NaiveDate, to: NaiveDate, first_sale: Option<NaiveDate>, event_days: &BTreeSet<NaiveDate>) -> Vec<NaiveDate> {
+    let from = first_sale.map_or(from, |first| from.max(first));
+    from.iter_days()
+        .take_while(|d| *d <= to)
+        .filter(|d| !event_days.contains(d))
+        .collect()
+}
+
+
+fn first_sale(days: &BTreeMap<String, SummaryRow>) -> Option<NaiveDate> {
+    days.keys().next().and_then(|d| app::parse_date(d).ok())
+}
+
+
+fn sum_dates(days: &BTreeMap<String, SummaryRow>, dates: &[NaiveDate]) -> SummaryRow {
+    let mut total = SummaryRow::default();
+    for day in dates.iter().filter_map(|d| days.get(&app::format_date(*d))) {
+        total.merge(day);
+    }
+    total
+}
+
+
+fn impact(appid: String, app_name: Option<String>, event: &SummaryRow, baseline: &SummaryRow, baseline_days: i64, start: NaiveDate, end: NaiveDate) -> AppImpact {
+    let event_days = (end - start).num_days() + 1;
+    let per_day = |total: f64, days: i64| if days > 0 { total / days as f64 } else { 0.0 };
+    let uplift = |during: f64, before: f64| if before > 0.0 { Some(during / before - 1.0) } else { None };
+
+    let event_units_per_day = per_day(event.net_units_sold as f64, event_days);
+    let event_sales_usd_per_day = per_day(event.net_sales_usd, event_days);
+    let baseline_units_per_day = per_day(baseline.net_units_sold as f64, baseline_days);
+    let baseline_sales_usd_per_day = per_day(baseline.net_sales_usd, baseline_days);
+
+    AppImpact {
+        appid,
+        app_name,
+        event_units: event.net_units_sold,
+        event_sales_usd: event.net_sales_usd,
+        event_units_per_day,
+        event_sales_usd_per_day,
+        baseline_days,
+        baseline_units_per_day,
+        baseline_sales_usd_per_day,
+        units_uplift: uplift(event_units_per_day, baseline_units_per_day),
+        sales_uplift: uplift(event_sales_usd_per_day, baseline_sales_usd_per_day),
+    }
+}
@@ -0,0 +1,169 @@
+use crate::annotation::{self, Annotation};
+use crate::app::{self, ErrorType};
+use crate::bundle::BundleAllocation;
+use crate::export::{self, ExportColumn, ExportProfile};
+use crate::report;
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use crate::summary::{self, GroupBy, SummaryRow};
+use serde::{Serialize, Deserialize};
+use std::fmt::Write;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardFormat {
+    Csv,
+    // Pastes straight into spreadsheet cells
+    Tsv,
+    Markdown,
+    Html,
+}
+
+// Text put on the clipboard, HTML also comes with a plain text fallback for apps that can't paste it
+pub struct ClipboardContent {
+    pub text: String,
+    pub html: Option<String>,
+}
+
+
+pub fn copy_rows(rows: &[CPartnerFinancialsDetailedSalesResult], format: ClipboardFormat, delimiter: u8, profile: Option<&ExportProfile>) -> Result<ClipboardContent, ErrorType> {
+    let records = match (format, profile) {
+        (_, Some(profile)) => export::apply_profile(rows, profile)?,
+        // Plain CSV keeps every field of the rows, as it always did
+        (ClipboardFormat::Csv, None) => return Ok(ClipboardContent { text: app::write_csv(rows, delimiter)?, html: None }),
+        (_, None) => export::apply_profile(rows, &all_columns_profile()?)?,
+    };
+    render(&records, format, delimiter)
+}
+
+
+// Aggregated rows with a total line, numbers are only formatted for humans in Markdown and HTML.
+// Daily summaries get the events of each day, like the Daily sheet of the workbook
+pub fn copy_summary(rows: &[CPartnerFinancialsDetailedSalesResult], group_by: GroupBy, bundles: &BundleAllocation, annotations: &[Annotation], format: ClipboardFormat, delimiter: u8) -> Result<ClipboardContent, ErrorType> {
+    let readable = matches!(format, ClipboardFormat::Markdown | ClipboardFormat::Html);
+    let mut summaries = summary::summarize_with_bundles(rows, group_by, bundles);
+    summaries.push(summary::total(rows));
+
+    let mut records = vec![
+        [group_by_header(group_by), "Name", "Units sold", "Units returned", "Net units", "Gross sales (USD)", "Returns (USD)", "Net sales (USD)", "Refund rate"]
+            .map(String::from)
+            .to_vec()
+    ];
+    let with_events = group_by == GroupBy::Date && !annotations.is_empty();
+    if with_events {
+        records[0].push("Events".into());
+    }
+    for s in summaries.iter() {
+        let mut record = summary_record(s, readable);
+        if with_events {
+            record.push(annotation::labels_on(annotations, &s.key).join(", "));
+        }
+        records.push(record);
+    }
+
+    render(&records, format, delimiter)
+}
+
+
+fn summary_record(s: &SummaryRow, readable: bool) -> Vec<String> {
+    let count = |value: i64| if readable { report::format_count(value) } else { value.to_string() };
+    let usd = |value: f64| if readable { report::format_usd(value) } else { format!("{:.2}", value) };
+    let rate = if readable { format!("{:.1}%", s.refund_rate() * 100.0) } else { format!("{:.4}", s.refund_rate()) };
+
+    vec![
+        s.key.clone(),
+        s.label.clone().unwrap_or_default(),
+        count(s.gross_units_sold),
+        count(s.gross_units_returned),
+        count(s.net_units_sold),
+        usd(s.gross_sales_usd),
+        usd(s.gross_returns_usd),
+        usd(s.net_sales_usd),
+        rate,
+    ]
+}
+
+
+fn group_by_header(group_by: GroupBy) -> &'static str {
+    match group_by {
+        GroupBy::Date => "Date",
+        GroupBy::App => "App",
+        GroupBy::Package => "Package",
+        GroupBy::Country => "Country",
+        GroupBy::Discount => "Discount",
+    }
+}
+
+
+// Every typed column, used when no profile is picked for the table formats
+fn all_columns_profile() -> Result<ExportProfile, ErrorType> {
+    let schema = export::record_batch(&[])?.schema();
+    Ok(ExportProfile {
+        id: None,
+        name: "All columns".into(),
+        columns: schema.fields().iter().map(|f| ExportColumn { field: f.name().clone(), label: None }).collect(),
+        decimal_places: None,
+        decimal_separator: ".".into(),
+        date_format: None,
+        null_value: String::new(),
+    })
+}
+
+
+// The first record is the header
+fn render(records: &[Vec<String>], format: ClipboardFormat, delimiter: u8) -> Result<ClipboardContent, ErrorType> {
+    let content = match format {
+        ClipboardFormat::Csv => ClipboardContent { text: app::write_csv(records, delimiter)?, html: None },
+        ClipboardFormat::Tsv => ClipboardContent { text: tsv(records), html: None },
+        ClipboardFormat::Markdown => ClipboardContent { text: markdown(records), html: None },
+        ClipboardFormat::Html => ClipboardContent { text: tsv(records), html: Some(html(records)) },
+    };
+    Ok(content)
+}
+
+
+fn tsv(records: &[Vec<String>]) -> String {
+    let mut text = String::new();
+    for record in records.iter() {
+        let cells: Vec<String> = record.iter().map(|c| c.replace(['\t', '\n', '\r'], " ")).collect();
+        text.push_str(&cells.join("\t"));
+        text.push('\n');
+    }
+    text
+}
+
+
+fn markdown(records: &[Vec<String>]) -> String {
+    let mut text = String::new();
+    for (i, record) in records.iter().enumerate() {
+        let cells: Vec<String> = record.iter().map(|c| c.replace('|', "\\|").replace(['\n', '\r'], " ")).collect();
+        let _ = writeln!(text, "| {} |", cells.join(" | "));
+        if i == 0 {
+            let _ = writeln!(text, "|{}|", vec!["---"; record.len()].join("|"));
+        }
+    }
+    text
+}
+
+
+fn html(records: &[Vec<String>]) -> String {
+    let mut text = String::from("<table>\n");
+    for (i, record) in records.iter().enumerate() {
+        let tag = if i == 0 { "th" } else { "td" };
+        text.push_str("<tr>");
+        for cell in record.iter() {
+            let _ = write!(text, "<{}>{}</{}>", tag, escape_html(cell), tag);
+        }
+        text.push_str("</tr>\n");
+    }
+    text.push_str("</table>\n");
+    text
+}
+
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,13 +1,24 @@
+use crate::api::ApiSettings;
 use crate::app;
-use tauri::{
-    AppHandle,
-    Manager,
-    path::BaseDirectory
-};
 use tokio_rusqlite::{params, Connection};
 use crate::app::{ErrorType, Settings};
 use crate::steam::DetailedSales;
 use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use crate::steam::ParseFailure;
+use crate::royalty::{RoyaltyPayee, RoyaltyPayout, RoyaltyRule, SalesTotals};
+use crate::export::ExportProfile;
+use crate::alert::{AlertRule, AlertScope};
+use crate::annotation::Annotation;
+use crate::anomaly::Anomaly;
+use crate::bundle::BundleRule;
+use crate::digest::DigestSettings;
+use crate::pricing::{DailyPrice, PricePeriod, ReferencePrice};
+use crate::discount::{CombinedDiscount, DiscountInfo};
+use crate::schedule::ExportSchedule;
+use crate::summary::SummaryRow;
+use crate::target::SalesTarget;
+use crate::webhook::Webhook;
+use std::collections::HashMap;
 
 
 pub async fn open() -> Result<Connection, ErrorType> {
@@ -23,19 +34,16 @@ pub async fn open() -> Result<Connection, ErrorType> {
 }
 
 
-pub async fn create(app_handle: AppHandle) -> Result<Connection, ErrorType> {
-    let sql_path = app_handle.path().resolve("steamboard.sql", BaseDirectory::Resource)
-        .map_err(|e| ErrorType::Missing(format!("Failed to resolve path to steamboard.sql: {}", e)))?;
+// Embedded so every binary creates the same database, with or without the bundled resources
+const STEAMBOARD_SQL: &str = include_str!("../steamboard.sql");
 
-    log::info!("Using SQL file at: {}", sql_path.display());
-
-    let steamboard_sql = std::fs::read_to_string(sql_path)
-        .map_err(|e| ErrorType::Missing(format!("Failed to read steamboard.sql: {}", e)))?;
 
+pub async fn create() -> Result<Connection, ErrorType> {
     let connection = open().await?;
 
     connection.call(move |conn| {
-        conn.execute_batch(&steamboard_sql)?;
+        conn.execute_batch(STEAMBOARD_SQL)?;
+        migrate(conn)?;
         conn.execute("INSERT INTO settings (id) VALUES (0) ON CONFLICT(id) DO NOTHING", params![])?;
         conn.execute("INSERT INTO steam_key_request_info (key_request_id) VALUES (0) ON CONFLICT(key_request_id) DO NOTHING", params![])?;
         Ok(())
@@ -47,6 +55,26 @@ pub async fn create(app_handle: AppHandle) -> Result<Connection, ErrorType> {
 }
 
 
+// Columns added after the first release, `CREATE TABLE IF NOT EXISTS` won't add them to existing databases
+fn migrate(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    add_column_if_missing(conn, "steam_results", "extra", "TEXT")?;
+    Ok(())
+}
+
+
+fn add_column_if_missing(conn: &rusqlite::Connection, table: &str, column: &str, definition: &str) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{}\")", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>("name"))?;
+    for name in columns {
+        if name? == column {
+            return Ok(());
+        }
+    }
+    conn.execute(&format!("ALTER TABLE \"{}\" ADD COLUMN \"{}\" {}", table, column, definition), params![])?;
+    Ok(())
+}
+
+
 pub async fn has_settings(connection: &Connection) -> Result<bool, ErrorType> {
     let count = connection.call(|conn| {
         let mut stmt = conn.prepare("SELECT COUNT(*) FROM settings WHERE id = 0 AND steam_api_key IS NOT NULL")?;
@@ -62,7 +90,22 @@ pub async fn has_settings(connection: &Connection) -> Result<bool, ErrorType> {
 
 pub async fn get_settings(connection: &Connection) -> Result<Settings, ErrorType> {
     let password = app::get_password().await?;
-    let mut settings = connection.call(|conn| {
+    let mut settings = get_encrypted_settings(connection).await?;
+
+    // Decrypt the API key if it exists
+    if let Some(encrypted_api_key) = &settings.steam_api_key {
+        let decrypted_key = app::decrypt(&password, encrypted_api_key)?;
+        settings.steam_api_key = Some(decrypted_key);
+    } else {
+        settings.steam_api_key = Some(String::new());
+    }
+
+    Ok(settings)
+}
+
+// Settings as stored, the API key is still encrypted
+pub async fn get_encrypted_settings(connection: &Connection) -> Result<Settings, ErrorType> {
+    let settings = connection.call(|conn| {
         let settings = conn.query_row("SELECT * FROM settings WHERE id = 0",
             params![],
             |row| {
@@ -78,14 +121,6 @@ pub async fn get_settings(connection: &Connection) -> Result<Settings, ErrorType
     .await
     .map_err(|e| ErrorType::BadRequest(format!("Failed to query settings: {}", e)))?;
 
-    // Decrypt the API key if it exists
-    if let Some(encrypted_api_key) = &settings.steam_api_key {
-        let decrypted_key = app::decrypt(&password, encrypted_api_key)?;
-        settings.steam_api_key = Some(decrypted_key);
-    } else {
-        settings.steam_api_key = Some(String::new());
-    }
-
     Ok(settings)
 }
 
@@ -121,6 +156,18 @@ pub async fn save_settings(connection: &Connection) -> Result<(), ErrorType> {
 }
 
 
+pub async fn save_highwatermark(connection: &Connection, highwatermark: String) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("UPDATE settings SET highwatermark = ?1 WHERE id = 0", params![highwatermark])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("Failed to update highwatermark: {}", e)))?;
+
+    Ok(())
+}
+
+
 pub async fn get_highwatermark(connection: &Connection, date: String) -> Result<i64, ErrorType> {
     let sale_dates = connection.call(|conn| {
         let mut stmt = conn.prepare("SELECT highwatermark_id FROM steam_dates WHERE date = ?1")?;
@@ -150,6 +197,9 @@ pub async fn delete_sale_detail(connection: &Connection, date: String) -> Result
         conn.execute(
             "DELETE FROM steam_results WHERE date = ?1",
             params![date])?;
+        conn.execute(
+            "DELETE FROM steam_parse_failures WHERE date = ?1",
+            params![date])?;
         Ok(())
     })
     .await
@@ -411,9 +461,10 @@ pub async fn insert_sale_detail(connection: &Connection, detailed_sales: Detaile
                             avg_sale_price_usd,
                             combined_discount_id,
                             primary_appid,
-                            additional_revenue_share_tier
+                            additional_revenue_share_tier,
+                            extra
                         )
-                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28)
                     ",
                     params![
                         result.partnerid,
@@ -443,6 +494,7 @@ pub async fn insert_sale_detail(connection: &Connection, detailed_sales: Detaile
                         result.combined_discount_id,
                         result.primary_appid,
                         result.additional_revenue_share_tier,
+                        result.extra,
                     ],
                 )?;
 
@@ -475,6 +527,7 @@ pub async fn insert_sale_detail(connection: &Connection, detailed_sales: Detaile
                 details.combined_discount_id = result.combined_discount_id.clone();
                 details.primary_appid = result.primary_appid.clone();
                 details.additional_revenue_share_tier = result.additional_revenue_share_tier.clone();
+                details.extra = result.extra.clone();
 
                 if let Some(p) = detail.partner_info.as_ref().unwrap_or(&Vec::new()).iter().find(|x| x.partnerid == result.partnerid) {
                     details.partner_name = p.partner_name.clone();
@@ -519,6 +572,26 @@ pub async fn insert_sale_detail(connection: &Connection, detailed_sales: Detaile
             }
         }
 
+        for failure in detail.parse_failures.iter() {
+            conn.execute(
+                "
+                    INSERT INTO steam_parse_failures (
+                        date,
+                        section,
+                        error,
+                        raw
+                    )
+                    VALUES (?1, ?2, ?3, ?4)
+                ",
+                params![
+                    failure.date,
+                    failure.section,
+                    failure.error,
+                    failure.raw,
+                ]
+            )?;
+        }
+
         Ok(aggregated_sales_details)
     })
     .await
@@ -527,127 +600,1602 @@ pub async fn insert_sale_detail(connection: &Connection, detailed_sales: Detaile
 }
 
 
+const SALE_DETAILS_QUERY: &str = "
+            SELECT
+                result.partnerid,
+                result.date,
+                result.line_item_type,
+                result.packageid,
+                result.bundleid,
+                result.appid,
+                result.game_item_id,
+                result.package_sale_type,
+                result.key_request_id,
+                result.platform,
+                result.country_code,
+                result.base_price,
+                result.sale_price,
+                result.currency,
+                result.gross_units_sold,
+                result.gross_units_returned,
+                result.gross_sales_usd,
+                result.gross_returns_usd,
+                result.net_tax_usd,
+                result.gross_units_activated,
+                result.view_grant_partnerid,
+                result.net_units_sold,
+                result.net_sales_usd,
+                result.avg_sale_price_usd,
+                result.combined_discount_id,
+                result.primary_appid,
+                result.additional_revenue_share_tier,
+                partner.partner_name,
+                package.package_name,
+                bundle.bundle_name,
+                app.app_name,
+                game_item.game_item_description,
+                game_item.game_item_category,
+                key_request.key_request_notes,
+                key_request.game_code_id,
+                key_request.game_code_description,
+                key_request.territory_code_id,
+                key_request.territory_code_description,
+                country.country_name,
+                country.region,
+                combined_discount.combined_discount_name,
+                combined_discount.total_discount_percentage,
+                combined_discount.discount_ids,
+                result.extra
+            FROM steam_results result
+                LEFT JOIN steam_partner_info partner on result.partnerid = partner.partnerid
+                LEFT JOIN steam_package_info package on result.packageid = package.packageid
+                LEFT JOIN steam_bundle_info bundle on result.bundleid = bundle.bundleid
+                LEFT JOIN steam_app_info app on result.appid = app.appid OR result.primary_appid = app.appid
+                LEFT JOIN steam_game_item_info game_item on result.game_item_id = game_item.game_item_id
+                LEFT JOIN steam_key_request_info key_request on result.key_request_id = key_request.key_request_id
+                LEFT JOIN steam_country_info country on result.country_code = country.country_code
+                LEFT JOIN steam_combined_discount_info combined_discount on result.combined_discount_id = combined_discount.combined_discount_id
+            WHERE date >= ?1 AND date <= ?2
+";
+
+
+fn sale_detail_from_row(row: &rusqlite::Row) -> Result<CPartnerFinancialsDetailedSalesResult, rusqlite::Error> {
+    Ok(CPartnerFinancialsDetailedSalesResult {
+        partnerid: row.get("partnerid")?,
+        date: row.get("date")?,
+        line_item_type: row.get("line_item_type")?,
+        packageid: row.get("packageid")?,
+        bundleid: row.get("bundleid")?,
+        appid: row.get("appid")?,
+        game_item_id: row.get("game_item_id")?,
+        package_sale_type: row.get("package_sale_type")?,
+        key_request_id: row.get("key_request_id")?,
+        platform: row.get("platform")?,
+        country_code: row.get("country_code")?,
+        base_price: row.get("base_price")?,
+        sale_price: row.get("sale_price")?,
+        currency: row.get("currency")?,
+        gross_units_sold: row.get("gross_units_sold")?,
+        gross_units_returned: row.get("gross_units_returned")?,
+        gross_sales_usd: row.get("gross_sales_usd")?,
+        gross_returns_usd: row.get("gross_returns_usd")?,
+        net_tax_usd: row.get("net_tax_usd")?,
+        gross_units_activated: row.get("gross_units_activated")?,
+        view_grant_partnerid: row.get("view_grant_partnerid")?,
+        net_units_sold: row.get("net_units_sold")?,
+        net_sales_usd: row.get("net_sales_usd")?,
+        avg_sale_price_usd: row.get("avg_sale_price_usd")?,
+        combined_discount_id: row.get("combined_discount_id")?,
+        primary_appid: row.get("primary_appid")?,
+        additional_revenue_share_tier: row.get("additional_revenue_share_tier")?,
+        partner_name: row.get("partner_name")?,
+        package_name: row.get("package_name")?,
+        bundle_name: row.get("bundle_name")?,
+        app_name: row.get("app_name")?,
+        game_item_description: row.get("game_item_description")?,
+        game_item_category: row.get("game_item_category")?,
+        key_request_notes: row.get("key_request_notes")?,
+        game_code_id: row.get("game_code_id")?,
+        game_code_description: row.get("game_code_description")?,
+        territory_code_id: row.get("territory_code_id")?,
+        territory_code_description: row.get("territory_code_description")?,
+        country_name: row.get("country_name")?,
+        region: row.get("region")?,
+        combined_discount_name: row.get("combined_discount_name")?,
+        total_discount_percentage: row.get("total_discount_percentage")?,
+        //discount_ids: row.get("discount_ids")?
+        extra: row.get("extra")?,
+    })
+}
+
+
 pub async fn get_sale_details_by_date(connection: &Connection, from_date: Option<String>, to_date: Option<String>) -> Result<Vec<CPartnerFinancialsDetailedSalesResult>, ErrorType> {
     let from_date = from_date.unwrap_or("1970-01-01".to_string());
     let to_date = to_date.unwrap_or("9999-12-31".to_string());
     let sale_details = connection.call(move |conn| {
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare(SALE_DETAILS_QUERY)?;
+        let sale_details_iter = stmt.query_map([from_date, to_date], sale_detail_from_row)?;
+
+        let mut sale_details = Vec::new();
+
+        for detail in sale_details_iter {
+            let det = detail?;
+            sale_details.push(det);
+        }
+        Ok(sale_details)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting sales details failed: {}", e)))?;
+
+    Ok(sale_details)
+}
+
+
+pub async fn count_sale_details(connection: &Connection, from_date: Option<String>, to_date: Option<String>) -> Result<usize, ErrorType> {
+    let from_date = from_date.unwrap_or("1970-01-01".to_string());
+    let to_date = to_date.unwrap_or("9999-12-31".to_string());
+    let count = connection.call(move |conn| {
+        let count: usize = conn.query_row("SELECT COUNT(*) FROM steam_results WHERE date >= ?1 AND date <= ?2", [from_date, to_date], |row| row.get(0))?;
+        Ok(count)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("counting sales details failed: {}", e)))?;
+
+    Ok(count)
+}
+
+
+// First and last day with sales, None when nothing was synced yet
+pub async fn get_sales_date_range(connection: &Connection) -> Result<(Option<String>, Option<String>), ErrorType> {
+    let range = connection.call(|conn| {
+        let range = conn.query_row("SELECT MIN(date), MAX(date) FROM steam_results", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(range)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting sales date range failed: {}", e)))?;
+
+    Ok(range)
+}
+
+
+// Hands the rows to `consume` one by one on the database thread, without loading them all in memory
+pub async fn with_sale_details<F, R>(connection: &Connection, from_date: Option<String>, to_date: Option<String>, consume: F) -> Result<R, ErrorType>
+where
+    F: FnOnce(&mut dyn Iterator<Item = Result<CPartnerFinancialsDetailedSalesResult, rusqlite::Error>>) -> Result<R, ErrorType> + Send + 'static,
+    R: Send + 'static,
+{
+    let from_date = from_date.unwrap_or("1970-01-01".to_string());
+    let to_date = to_date.unwrap_or("9999-12-31".to_string());
+    let result = connection.call(move |conn| {
+        let mut stmt = conn.prepare(SALE_DETAILS_QUERY)?;
+        let mut sale_details_iter = stmt.query_map([from_date, to_date], sale_detail_from_row)?;
+        Ok(consume(&mut sale_details_iter))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting sales details failed: {}", e)))?;
+
+    result
+}
+
+
+pub async fn get_parse_failures(connection: &Connection, from_date: Option<String>, to_date: Option<String>) -> Result<Vec<ParseFailure>, ErrorType> {
+    let from_date = from_date.unwrap_or("1970-01-01".to_string());
+    let to_date = to_date.unwrap_or("9999-12-31".to_string());
+    let parse_failures = connection.call(move |conn| {
+        let mut stmt = conn.prepare("SELECT date, section, error, raw FROM steam_parse_failures WHERE date >= ?1 AND date <= ?2 ORDER BY date")?;
+        let parse_failures_iter = stmt.query_map([from_date, to_date], |row| {
+            Ok(ParseFailure {
+                date: row.get("date")?,
+                section: row.get("section")?,
+                error: row.get("error")?,
+                raw: row.get("raw")?,
+            })
+        })?;
+
+        let mut parse_failures = Vec::new();
+        for failure in parse_failures_iter {
+            parse_failures.push(failure?);
+        }
+        Ok(parse_failures)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting parse failures failed: {}", e)))?;
+
+    Ok(parse_failures)
+}
+
+
+pub async fn get_sales_totals(connection: &Connection, appid: Option<i32>, packageid: Option<i32>, from_date: Option<String>, to_date: Option<String>) -> Result<SalesTotals, ErrorType> {
+    let from_date = from_date.unwrap_or("1970-01-01".to_string());
+    let to_date = to_date.unwrap_or("9999-12-31".to_string());
+    let totals = connection.call(move |conn| {
+        let totals = conn.query_row(
             "
                 SELECT
-                    result.partnerid,
-                    result.date,
-                    result.line_item_type,
-                    result.packageid,
-                    result.bundleid,
-                    result.appid,
-                    result.game_item_id,
-                    result.package_sale_type,
-                    result.key_request_id,
-                    result.platform,
-                    result.country_code,
-                    result.base_price,
-                    result.sale_price,
-                    result.currency,
-                    result.gross_units_sold,
-                    result.gross_units_returned,
-                    result.gross_sales_usd,
-                    result.gross_returns_usd,
-                    result.net_tax_usd,
-                    result.gross_units_activated,
-                    result.view_grant_partnerid,
-                    result.net_units_sold,
-                    result.net_sales_usd,
-                    result.avg_sale_price_usd,
-                    result.combined_discount_id,
-                    result.primary_appid,
-                    result.additional_revenue_share_tier,
-                    partner.partner_name,
-                    package.package_name,
-                    bundle.bundle_name,
-                    app.app_name,
-                    game_item.game_item_description,
-                    game_item.game_item_category,
-                    key_request.key_request_notes,
-                    key_request.game_code_id,
-                    key_request.game_code_description,
-                    key_request.territory_code_id,
-                    key_request.territory_code_description,
-                    country.country_name,
-                    country.region,
-                    combined_discount.combined_discount_name,
-                    combined_discount.total_discount_percentage,
-                    combined_discount.discount_ids
-                FROM steam_results result
-                    LEFT JOIN steam_partner_info partner on result.partnerid = partner.partnerid
-                    LEFT JOIN steam_package_info package on result.packageid = package.packageid
-                    LEFT JOIN steam_bundle_info bundle on result.bundleid = bundle.bundleid
-                    LEFT JOIN steam_app_info app on result.appid = app.appid OR result.primary_appid = app.appid
-                    LEFT JOIN steam_game_item_info game_item on result.game_item_id = game_item.game_item_id
-                    LEFT JOIN steam_key_request_info key_request on result.key_request_id = key_request.key_request_id
-                    LEFT JOIN steam_country_info country on result.country_code = country.country_code
-                    LEFT JOIN steam_combined_discount_info combined_discount on result.combined_discount_id = combined_discount.combined_discount_id
-                WHERE date >= ?1 AND date <= ?2
+                    COALESCE(SUM(net_units_sold), 0),
+                    COALESCE(SUM(gross_sales_usd), 0),
+                    COALESCE(SUM(net_sales_usd), 0)
+                FROM steam_results
+                WHERE (?1 IS NULL OR appid = ?1 OR primary_appid = ?1)
+                    AND (?2 IS NULL OR packageid = ?2)
+                    AND date >= ?3 AND date <= ?4
+            ",
+            params![appid, packageid, from_date, to_date],
+            |row| {
+                Ok(SalesTotals {
+                    net_units_sold: row.get(0)?,
+                    gross_sales_usd: row.get(1)?,
+                    net_sales_usd: row.get(2)?,
+                })
+            })?;
+        Ok(totals)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting sales totals failed: {}", e)))?;
+
+    Ok(totals)
+}
+
+
+pub async fn get_royalty_payees(connection: &Connection) -> Result<Vec<RoyaltyPayee>, ErrorType> {
+    let payees = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT id, name, threshold_usd FROM royalty_payees ORDER BY name")?;
+        let payees_iter = stmt.query_map([], |row| {
+            Ok(RoyaltyPayee {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                threshold_usd: row.get("threshold_usd")?,
+            })
+        })?;
+
+        let mut payees = Vec::new();
+        for payee in payees_iter {
+            payees.push(payee?);
+        }
+        Ok(payees)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting royalty payees failed: {}", e)))?;
+
+    Ok(payees)
+}
+
+
+pub async fn save_royalty_payee(connection: &Connection, payee: RoyaltyPayee) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        conn.execute(
             "
+                INSERT INTO royalty_payees (id, name, threshold_usd)
+                VALUES (?1, ?2, ?3)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    name = ?2,
+                    threshold_usd = ?3
+            ",
+            params![payee.id, payee.name, payee.threshold_usd]
         )?;
+        Ok(payee.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving royalty payee failed: {}", e)))?;
 
-        let sale_details_iter = stmt.query_map([from_date, to_date], |row| {
-            Ok(CPartnerFinancialsDetailedSalesResult {
-                partnerid: row.get("partnerid")?,
-                date: row.get("date")?,
-                line_item_type: row.get("line_item_type")?,
-                packageid: row.get("packageid")?,
-                bundleid: row.get("bundleid")?,
+    Ok(id)
+}
+
+
+pub async fn delete_royalty_payee(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM royalty_rules WHERE payee_id = ?1", params![id])?;
+        conn.execute("DELETE FROM royalty_payouts WHERE payee_id = ?1", params![id])?;
+        conn.execute("DELETE FROM royalty_payees WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting royalty payee failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn get_royalty_rules(connection: &Connection) -> Result<Vec<RoyaltyRule>, ErrorType> {
+    let rules = connection.call(|conn| {
+        let mut stmt = conn.prepare(
+            "
+                SELECT
+                    id,
+                    payee_id,
+                    appid,
+                    packageid,
+                    percentage,
+                    valve_fee_percentage,
+                    advance_usd,
+                    start_date,
+                    end_date
+                FROM royalty_rules
+                ORDER BY payee_id, id
+            "
+        )?;
+        let rules_iter = stmt.query_map([], |row| {
+            Ok(RoyaltyRule {
+                id: row.get("id")?,
+                payee_id: row.get("payee_id")?,
                 appid: row.get("appid")?,
-                game_item_id: row.get("game_item_id")?,
-                package_sale_type: row.get("package_sale_type")?,
-                key_request_id: row.get("key_request_id")?,
-                platform: row.get("platform")?,
-                country_code: row.get("country_code")?,
-                base_price: row.get("base_price")?,
-                sale_price: row.get("sale_price")?,
-                currency: row.get("currency")?,
-                gross_units_sold: row.get("gross_units_sold")?,
-                gross_units_returned: row.get("gross_units_returned")?,
-                gross_sales_usd: row.get("gross_sales_usd")?,
-                gross_returns_usd: row.get("gross_returns_usd")?,
-                net_tax_usd: row.get("net_tax_usd")?,
-                gross_units_activated: row.get("gross_units_activated")?,
-                view_grant_partnerid: row.get("view_grant_partnerid")?,
-                net_units_sold: row.get("net_units_sold")?,
-                net_sales_usd: row.get("net_sales_usd")?,
-                avg_sale_price_usd: row.get("avg_sale_price_usd")?,
-                combined_discount_id: row.get("combined_discount_id")?,
-                primary_appid: row.get("primary_appid")?,
-                additional_revenue_share_tier: row.get("additional_revenue_share_tier")?,
-                partner_name: row.get("partner_name")?,
-                package_name: row.get("package_name")?,
-                bundle_name: row.get("bundle_name")?,
-                app_name: row.get("app_name")?,
-                game_item_description: row.get("game_item_description")?,
-                game_item_category: row.get("game_item_category")?,
-                key_request_notes: row.get("key_request_notes")?,
-                game_code_id: row.get("game_code_id")?,
-                game_code_description: row.get("game_code_description")?,
-                territory_code_id: row.get("territory_code_id")?,
-                territory_code_description: row.get("territory_code_description")?,
-                country_name: row.get("country_name")?,
-                region: row.get("region")?,
-                combined_discount_name: row.get("combined_discount_name")?,
-                total_discount_percentage: row.get("total_discount_percentage")?,
-                //discount_ids: row.get("discount_ids")?
+                packageid: row.get("packageid")?,
+                percentage: row.get("percentage")?,
+                valve_fee_percentage: row.get("valve_fee_percentage")?,
+                advance_usd: row.get("advance_usd")?,
+                start_date: row.get("start_date")?,
+                end_date: row.get("end_date")?,
             })
         })?;
 
-        let mut sale_details = Vec::new();
+        let mut rules = Vec::new();
+        for rule in rules_iter {
+            rules.push(rule?);
+        }
+        Ok(rules)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting royalty rules failed: {}", e)))?;
 
-        for detail in sale_details_iter {
-            let det = detail?;
-            sale_details.push(det);
+    Ok(rules)
+}
+
+
+pub async fn save_royalty_rule(connection: &Connection, rule: RoyaltyRule) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO royalty_rules (
+                    id,
+                    payee_id,
+                    appid,
+                    packageid,
+                    percentage,
+                    valve_fee_percentage,
+                    advance_usd,
+                    start_date,
+                    end_date
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    payee_id = ?2,
+                    appid = ?3,
+                    packageid = ?4,
+                    percentage = ?5,
+                    valve_fee_percentage = ?6,
+                    advance_usd = ?7,
+                    start_date = ?8,
+                    end_date = ?9
+            ",
+            params![
+                rule.id,
+                rule.payee_id,
+                rule.appid,
+                rule.packageid,
+                rule.percentage,
+                rule.valve_fee_percentage,
+                rule.advance_usd,
+                rule.start_date,
+                rule.end_date,
+            ]
+        )?;
+        Ok(rule.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving royalty rule failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn delete_royalty_rule(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM royalty_rules WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting royalty rule failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn get_royalty_payouts(connection: &Connection) -> Result<Vec<RoyaltyPayout>, ErrorType> {
+    let payouts = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT id, payee_id, to_date, amount_usd FROM royalty_payouts ORDER BY to_date, id")?;
+        let payouts_iter = stmt.query_map([], |row| {
+            Ok(RoyaltyPayout {
+                id: row.get("id")?,
+                payee_id: row.get("payee_id")?,
+                to_date: row.get("to_date")?,
+                amount_usd: row.get("amount_usd")?,
+            })
+        })?;
+
+        let mut payouts = Vec::new();
+        for payout in payouts_iter {
+            payouts.push(payout?);
         }
-        Ok(sale_details)
+        Ok(payouts)
     })
     .await
-    .map_err(|e| ErrorType::BadRequest(format!("getting sales details failed: {}", e)))?;
+    .map_err(|e| ErrorType::BadRequest(format!("getting royalty payouts failed: {}", e)))?;
 
-    Ok(sale_details)
+    Ok(payouts)
+}
+
+
+pub async fn save_royalty_payout(connection: &Connection, payout: RoyaltyPayout) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO royalty_payouts (id, payee_id, to_date, amount_usd)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    payee_id = ?2,
+                    to_date = ?3,
+                    amount_usd = ?4
+            ",
+            params![payout.id, payout.payee_id, payout.to_date, payout.amount_usd]
+        )?;
+        Ok(payout.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving royalty payout failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn delete_royalty_payout(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM royalty_payouts WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting royalty payout failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn get_export_profiles(connection: &Connection) -> Result<Vec<ExportProfile>, ErrorType> {
+    let profiles = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT id, name, columns, decimal_places, decimal_separator, date_format, null_value FROM export_profiles ORDER BY name")?;
+        let profiles_iter = stmt.query_map([], |row| {
+            let columns: String = row.get("columns")?;
+            Ok(ExportProfile {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                columns: serde_json::from_str(&columns).unwrap_or_default(),
+                decimal_places: row.get("decimal_places")?,
+                decimal_separator: row.get("decimal_separator")?,
+                date_format: row.get("date_format")?,
+                null_value: row.get("null_value")?,
+            })
+        })?;
+
+        let mut profiles = Vec::new();
+        for profile in profiles_iter {
+            profiles.push(profile?);
+        }
+        Ok(profiles)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting export profiles failed: {}", e)))?;
+
+    Ok(profiles)
+}
+
+
+pub async fn get_export_profile(connection: &Connection, id: i64) -> Result<ExportProfile, ErrorType> {
+    let profiles = get_export_profiles(connection).await?;
+    profiles.into_iter()
+        .find(|p| p.id == Some(id))
+        .ok_or(ErrorType::Missing(format!("Export profile {} not found", id)))
+}
+
+
+pub async fn save_export_profile(connection: &Connection, profile: ExportProfile) -> Result<i64, ErrorType> {
+    let columns = serde_json::to_string(&profile.columns)
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid export columns: {}", e)))?;
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO export_profiles (
+                    id,
+                    name,
+                    columns,
+                    decimal_places,
+                    decimal_separator,
+                    date_format,
+                    null_value
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    name = ?2,
+                    columns = ?3,
+                    decimal_places = ?4,
+                    decimal_separator = ?5,
+                    date_format = ?6,
+                    null_value = ?7
+            ",
+            params![
+                profile.id,
+                profile.name,
+                columns,
+                profile.decimal_places,
+                profile.decimal_separator,
+                profile.date_format,
+                profile.null_value,
+            ]
+        )?;
+        Ok(profile.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving export profile failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn delete_export_profile(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("UPDATE export_schedules SET profile_id = NULL WHERE profile_id = ?1", params![id])?;
+        conn.execute("DELETE FROM export_profiles WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting export profile failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+// Enums are stored as their serde name, `previous_month` rather than `"previous_month"`
+fn enum_to_text<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value).ok()
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+
+fn enum_from_text<T: serde::de::DeserializeOwned>(row: &rusqlite::Row, column: &str) -> Result<T, rusqlite::Error> {
+    let text: String = row.get(column)?;
+    serde_json::from_value(serde_json::Value::String(text))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+
+pub async fn get_export_schedules(connection: &Connection) -> Result<Vec<ExportSchedule>, ErrorType> {
+    let schedules = connection.call(|conn| {
+        let mut stmt = conn.prepare(
+            "
+                SELECT
+                    id,
+                    name,
+                    enabled,
+                    frequency,
+                    range,
+                    format,
+                    profile_id,
+                    delimiter,
+                    compression,
+                    directory,
+                    filename_template,
+                    last_run,
+                    last_error
+                FROM export_schedules
+                ORDER BY name
+            "
+        )?;
+        let schedules_iter = stmt.query_map([], |row| {
+            Ok(ExportSchedule {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                enabled: row.get("enabled")?,
+                frequency: enum_from_text(row, "frequency")?,
+                range: enum_from_text(row, "range")?,
+                format: enum_from_text(row, "format")?,
+                profile_id: row.get("profile_id")?,
+                delimiter: row.get("delimiter")?,
+                compression: enum_from_text(row, "compression")?,
+                directory: row.get("directory")?,
+                filename_template: row.get("filename_template")?,
+                last_run: row.get("last_run")?,
+                last_error: row.get("last_error")?,
+            })
+        })?;
+
+        let mut schedules = Vec::new();
+        for schedule in schedules_iter {
+            schedules.push(schedule?);
+        }
+        Ok(schedules)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting export schedules failed: {}", e)))?;
+
+    Ok(schedules)
+}
+
+
+pub async fn get_export_schedule(connection: &Connection, id: i64) -> Result<ExportSchedule, ErrorType> {
+    let schedules = get_export_schedules(connection).await?;
+    schedules.into_iter()
+        .find(|s| s.id == Some(id))
+        .ok_or(ErrorType::Missing(format!("Export schedule {} not found", id)))
+}
+
+
+pub async fn save_export_schedule(connection: &Connection, schedule: ExportSchedule) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO export_schedules (
+                    id,
+                    name,
+                    enabled,
+                    frequency,
+                    range,
+                    format,
+                    profile_id,
+                    delimiter,
+                    compression,
+                    directory,
+                    filename_template,
+                    last_run,
+                    last_error
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    name = ?2,
+                    enabled = ?3,
+                    frequency = ?4,
+                    range = ?5,
+                    format = ?6,
+                    profile_id = ?7,
+                    delimiter = ?8,
+                    compression = ?9,
+                    directory = ?10,
+                    filename_template = ?11,
+                    last_run = ?12,
+                    last_error = ?13
+            ",
+            params![
+                schedule.id,
+                schedule.name,
+                schedule.enabled,
+                enum_to_text(&schedule.frequency),
+                enum_to_text(&schedule.range),
+                enum_to_text(&schedule.format),
+                schedule.profile_id,
+                schedule.delimiter,
+                enum_to_text(&schedule.compression),
+                schedule.directory,
+                schedule.filename_template,
+                schedule.last_run,
+                schedule.last_error,
+            ]
+        )?;
+        Ok(schedule.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving export schedule failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+// `error` is None when the run succeeded
+pub async fn set_export_schedule_last_run(connection: &Connection, id: i64, date: String, error: Option<String>) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("UPDATE export_schedules SET last_run = ?2, last_error = ?3 WHERE id = ?1", params![id, date, error])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("updating export schedule failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn delete_export_schedule(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM export_schedules WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting export schedule failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn get_api_settings(connection: &Connection) -> Result<ApiSettings, ErrorType> {
+    let settings = connection.call(|conn| {
+        let settings = conn.query_row("SELECT enabled, port, token, metrics FROM api_settings WHERE id = 0", [], |row| {
+            Ok(ApiSettings {
+                enabled: row.get("enabled")?,
+                port: row.get("port")?,
+                token: row.get("token")?,
+                metrics: row.get("metrics")?,
+            })
+        });
+        match settings {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(ApiSettings::default()),
+            settings => Ok(settings?),
+        }
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting API settings failed: {}", e)))?;
+
+    Ok(settings)
+}
+
+
+pub async fn save_api_settings(connection: &Connection, settings: ApiSettings) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO api_settings (id, enabled, port, token, metrics)
+                VALUES (0, ?1, ?2, ?3, ?4)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    enabled = ?1,
+                    port = ?2,
+                    token = ?3,
+                    metrics = ?4
+            ",
+            params![settings.enabled, settings.port, settings.token, settings.metrics]
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving API settings failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn get_webhooks(connection: &Connection) -> Result<Vec<Webhook>, ErrorType> {
+    let webhooks = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT id, name, url, format, events, threshold_usd, enabled FROM webhooks ORDER BY name")?;
+        let webhooks_iter = stmt.query_map([], |row| {
+            let events: String = row.get("events")?;
+            Ok(Webhook {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                url: row.get("url")?,
+                format: enum_from_text(row, "format")?,
+                events: serde_json::from_str(&events).unwrap_or_default(),
+                threshold_usd: row.get("threshold_usd")?,
+                enabled: row.get("enabled")?,
+            })
+        })?;
+
+        let mut webhooks = Vec::new();
+        for webhook in webhooks_iter {
+            webhooks.push(webhook?);
+        }
+        Ok(webhooks)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting webhooks failed: {}", e)))?;
+
+    Ok(webhooks)
+}
+
+
+pub async fn save_webhook(connection: &Connection, webhook: Webhook) -> Result<i64, ErrorType> {
+    let events = serde_json::to_string(&webhook.events)
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid webhook events: {}", e)))?;
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO webhooks (
+                    id,
+                    name,
+                    url,
+                    format,
+                    events,
+                    threshold_usd,
+                    enabled
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    name = ?2,
+                    url = ?3,
+                    format = ?4,
+                    events = ?5,
+                    threshold_usd = ?6,
+                    enabled = ?7
+            ",
+            params![
+                webhook.id,
+                webhook.name,
+                webhook.url,
+                enum_to_text(&webhook.format),
+                events,
+                webhook.threshold_usd,
+                webhook.enabled,
+            ]
+        )?;
+        Ok(webhook.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving webhook failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn delete_webhook(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting webhook failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+// Digest settings as stored, the SMTP password is still encrypted
+pub async fn get_encrypted_digest_settings(connection: &Connection) -> Result<DigestSettings, ErrorType> {
+    connection.call(|conn| {
+        let settings = conn.query_row("SELECT * FROM digest_settings WHERE id = 0", [], |row| {
+            let recipients: String = row.get("recipients")?;
+            let password: Option<String> = row.get("password")?;
+            Ok(DigestSettings {
+                enabled: row.get("enabled")?,
+                host: row.get("host")?,
+                port: row.get("port")?,
+                security: enum_from_text(row, "security")?,
+                username: row.get("username")?,
+                password: password.unwrap_or_default(),
+                sender: row.get("sender")?,
+                recipients: serde_json::from_str(&recipients).unwrap_or_default(),
+                frequency: enum_from_text(row, "frequency")?,
+                hour: row.get("hour")?,
+                last_sent: row.get("last_sent")?,
+            })
+        });
+        match settings {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(DigestSettings::default()),
+            settings => Ok(settings?),
+        }
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting digest settings failed: {}", e)))
+}
+
+
+// The SMTP password is decrypted with the keyring password, only when there is one
+pub async fn get_digest_settings(connection: &Connection) -> Result<DigestSettings, ErrorType> {
+    let settings = get_encrypted_digest_settings(connection).await?;
+    if settings.password.is_empty() {
+        return Ok(settings);
+    }
+    let password = app::get_password().await?;
+    Ok(DigestSettings { password: app::decrypt(&password, &settings.password)?, ..settings })
+}
+
+
+pub async fn save_digest_settings(connection: &Connection, settings: DigestSettings) -> Result<(), ErrorType> {
+    let encrypted_password = if settings.password.is_empty() {
+        None
+    } else {
+        let password = app::get_password().await?;
+        Some(app::encrypt(&password, &settings.password)?)
+    };
+    let recipients = serde_json::to_string(&settings.recipients)
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid recipients: {}", e)))?;
+
+    connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO digest_settings (id, enabled, host, port, security, username, password, sender, recipients, frequency, hour, last_sent)
+                VALUES (0, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    enabled = ?1,
+                    host = ?2,
+                    port = ?3,
+                    security = ?4,
+                    username = ?5,
+                    password = ?6,
+                    sender = ?7,
+                    recipients = ?8,
+                    frequency = ?9,
+                    hour = ?10,
+                    last_sent = ?11
+            ",
+            params![
+                settings.enabled,
+                settings.host,
+                settings.port,
+                enum_to_text(&settings.security),
+                settings.username,
+                encrypted_password,
+                settings.sender,
+                recipients,
+                enum_to_text(&settings.frequency),
+                settings.hour,
+                settings.last_sent,
+            ]
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving digest settings failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn set_digest_last_sent(connection: &Connection, last_sent: String) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("UPDATE digest_settings SET last_sent = ?1 WHERE id = 0", params![last_sent])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("updating digest last sent failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn get_alert_rules(connection: &Connection) -> Result<Vec<AlertRule>, ErrorType> {
+    let rules = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT * FROM alert_rules ORDER BY name")?;
+        let rules_iter = stmt.query_map([], |row| {
+            Ok(AlertRule {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                enabled: row.get("enabled")?,
+                scope: enum_from_text(row, "scope")?,
+                scope_key: row.get("scope_key")?,
+                metric: enum_from_text(row, "metric")?,
+                period: enum_from_text(row, "period")?,
+                threshold: row.get("threshold")?,
+                message: row.get("message")?,
+                last_fired: row.get("last_fired")?,
+            })
+        })?;
+
+        let mut rules = Vec::new();
+        for rule in rules_iter {
+            rules.push(rule?);
+        }
+        Ok(rules)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting alert rules failed: {}", e)))?;
+
+    Ok(rules)
+}
+
+
+pub async fn save_alert_rule(connection: &Connection, rule: AlertRule) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO alert_rules (
+                    id,
+                    name,
+                    enabled,
+                    scope,
+                    scope_key,
+                    metric,
+                    period,
+                    threshold,
+                    message,
+                    last_fired
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    name = ?2,
+                    enabled = ?3,
+                    scope = ?4,
+                    scope_key = ?5,
+                    metric = ?6,
+                    period = ?7,
+                    threshold = ?8,
+                    message = ?9,
+                    last_fired = ?10
+            ",
+            params![
+                rule.id,
+                rule.name,
+                rule.enabled,
+                enum_to_text(&rule.scope),
+                rule.scope_key,
+                enum_to_text(&rule.metric),
+                enum_to_text(&rule.period),
+                rule.threshold,
+                rule.message,
+                rule.last_fired,
+            ]
+        )?;
+        Ok(rule.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving alert rule failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn set_alert_rule_last_fired(connection: &Connection, id: i64, last_fired: String) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("UPDATE alert_rules SET last_fired = ?2 WHERE id = ?1", params![id, last_fired])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("updating alert rule failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn delete_alert_rule(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM alert_rules WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting alert rule failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+// Totals of the rows in the scope, keys are compared as text like the summary keys
+pub async fn get_alert_totals(connection: &Connection, scope: AlertScope, scope_key: Option<String>, from_date: Option<String>, to_date: String) -> Result<SummaryRow, ErrorType> {
+    let from_date = from_date.unwrap_or("1970-01-01".to_string());
+    let column = match scope {
+        AlertScope::All => "NULL",
+        AlertScope::App => "COALESCE(appid, primary_appid)",
+        AlertScope::Package => "packageid",
+        AlertScope::Country => "country_code",
+    };
+    let query = format!(
+        "
+            SELECT
+                COALESCE(SUM(gross_units_sold), 0),
+                COALESCE(SUM(gross_units_returned), 0),
+                COALESCE(SUM(gross_units_activated), 0),
+                COALESCE(SUM(net_units_sold), 0),
+                COALESCE(SUM(gross_sales_usd), 0),
+                COALESCE(SUM(gross_returns_usd), 0),
+                COALESCE(SUM(net_tax_usd), 0),
+                COALESCE(SUM(net_sales_usd), 0)
+            FROM steam_results
+            WHERE (?1 IS NULL OR CAST({} AS TEXT) = ?1 COLLATE NOCASE)
+                AND date >= ?2 AND date <= ?3
+        ",
+        column
+    );
+    let totals = connection.call(move |conn| {
+        let totals = conn.query_row(&query, params![scope_key, from_date, to_date], |row| {
+            Ok(SummaryRow {
+                key: "total".into(),
+                label: None,
+                gross_units_sold: row.get(0)?,
+                gross_units_returned: row.get(1)?,
+                gross_units_activated: row.get(2)?,
+                net_units_sold: row.get(3)?,
+                gross_sales_usd: row.get(4)?,
+                gross_returns_usd: row.get(5)?,
+                net_tax_usd: row.get(6)?,
+                net_sales_usd: row.get(7)?,
+            })
+        })?;
+        Ok(totals)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting alert totals failed: {}", e)))?;
+
+    Ok(totals)
+}
+
+
+// Returns false when the anomaly was already notified
+pub async fn insert_notified_anomaly(connection: &Connection, anomaly: &Anomaly) -> Result<bool, ErrorType> {
+    let (date, appid, metric) = (anomaly.date.clone(), anomaly.appid.clone(), enum_to_text(&anomaly.metric));
+    let inserted = connection.call(move |conn| {
+        let count = conn.execute("INSERT OR IGNORE INTO notified_anomalies (date, appid, metric) VALUES (?1, ?2, ?3)", params![date, appid, metric])?;
+        Ok(count > 0)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving notified anomaly failed: {}", e)))?;
+
+    Ok(inserted)
+}
+
+
+pub async fn get_sales_targets(connection: &Connection) -> Result<Vec<SalesTarget>, ErrorType> {
+    let targets = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT * FROM sales_targets ORDER BY name")?;
+        let targets_iter = stmt.query_map([], |row| {
+            Ok(SalesTarget {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                appid: row.get("appid")?,
+                metric: enum_from_text(row, "metric")?,
+                period: enum_from_text(row, "period")?,
+                period_date: row.get("period_date")?,
+                value: row.get("value")?,
+                notify: row.get("notify")?,
+                last_notified: row.get("last_notified")?,
+            })
+        })?;
+
+        let mut targets = Vec::new();
+        for target in targets_iter {
+            targets.push(target?);
+        }
+        Ok(targets)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting sales targets failed: {}", e)))?;
+
+    Ok(targets)
+}
+
+
+pub async fn save_sales_target(connection: &Connection, target: SalesTarget) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO sales_targets (
+                    id,
+                    name,
+                    appid,
+                    metric,
+                    period,
+                    period_date,
+                    value,
+                    notify,
+                    last_notified
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    name = ?2,
+                    appid = ?3,
+                    metric = ?4,
+                    period = ?5,
+                    period_date = ?6,
+                    value = ?7,
+                    notify = ?8,
+                    last_notified = ?9
+            ",
+            params![
+                target.id,
+                target.name,
+                target.appid,
+                enum_to_text(&target.metric),
+                enum_to_text(&target.period),
+                target.period_date,
+                target.value,
+                target.notify,
+                target.last_notified,
+            ]
+        )?;
+        Ok(target.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving sales target failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn set_sales_target_last_notified(connection: &Connection, id: i64, last_notified: String) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("UPDATE sales_targets SET last_notified = ?2 WHERE id = ?1", params![id, last_notified])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("updating sales target failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+pub async fn delete_sales_target(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM sales_targets WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting sales target failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+fn annotation_from_row(row: &rusqlite::Row) -> Result<Annotation, rusqlite::Error> {
+    Ok(Annotation {
+        id: row.get("id")?,
+        from_date: row.get("from_date")?,
+        to_date: row.get("to_date")?,
+        appid: row.get("appid")?,
+        label: row.get("label")?,
+        category: enum_from_text(row, "category")?,
+    })
+}
+
+
+// Annotations overlapping the range, even partially
+pub async fn get_annotations(connection: &Connection, from_date: Option<String>, to_date: Option<String>) -> Result<Vec<Annotation>, ErrorType> {
+    let from_date = from_date.unwrap_or("1970-01-01".to_string());
+    let to_date = to_date.unwrap_or("9999-12-31".to_string());
+    let annotations = connection.call(move |conn| {
+        let mut stmt = conn.prepare("SELECT * FROM annotations WHERE to_date >= ?1 AND from_date <= ?2 ORDER BY from_date, id")?;
+        let annotations_iter = stmt.query_map([from_date, to_date], annotation_from_row)?;
+
+        let mut annotations = Vec::new();
+        for annotation in annotations_iter {
+            annotations.push(annotation?);
+        }
+        Ok(annotations)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting annotations failed: {}", e)))?;
+
+    Ok(annotations)
+}
+
+
+pub async fn save_annotation(connection: &Connection, annotation: Annotation) -> Result<i64, ErrorType> {
+    let id = connection.call(move |conn| {
+        Ok(upsert_annotation(conn, &annotation)?)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving annotation failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+// All or nothing, a failing line doesn't leave half an import behind
+pub async fn save_annotations(connection: &Connection, annotations: Vec<Annotation>) -> Result<usize, ErrorType> {
+    let count = connection.call(move |conn| {
+        let tx = conn.transaction()?;
+        for annotation in annotations.iter() {
+            upsert_annotation(&tx, annotation)?;
+        }
+        tx.commit()?;
+        Ok(annotations.len())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("importing annotations failed: {}", e)))?;
+
+    Ok(count)
+}
+
+
+fn upsert_annotation(conn: &rusqlite::Connection, annotation: &Annotation) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        "
+            INSERT INTO annotations (id, from_date, to_date, appid, label, category)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (id) DO
+            UPDATE SET
+                from_date = ?2,
+                to_date = ?3,
+                appid = ?4,
+                label = ?5,
+                category = ?6
+        ",
+        params![
+            annotation.id,
+            annotation.from_date,
+            annotation.to_date,
+            annotation.appid,
+            annotation.label,
+            enum_to_text(&annotation.category),
+        ]
+    )?;
+    Ok(annotation.id.unwrap_or(conn.last_insert_rowid()))
+}
+
+
+pub async fn delete_annotation(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM annotations WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting annotation failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+// Combined discounts with the discounts they stack, `discount_ids` is a JSON array
+pub async fn get_combined_discounts(connection: &Connection) -> Result<Vec<CombinedDiscount>, ErrorType> {
+    let combined_discounts = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT * FROM steam_discount_info")?;
+        let discounts: HashMap<i32, DiscountInfo> = stmt.query_map([], |row| {
+            Ok(DiscountInfo {
+                discountid: row.get("discountid")?,
+                description: row.get("discount_description")?,
+                group: row.get("discount_group")?,
+                percentage: row.get("discount_percentage")?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|d| (d.discountid, d))
+        .collect();
+
+        let mut stmt = conn.prepare("SELECT * FROM steam_combined_discount_info ORDER BY combined_discount_id")?;
+        let combined_iter = stmt.query_map([], |row| {
+            let discount_ids: Option<String> = row.get("discount_ids")?;
+            let discount_ids: Vec<i32> = discount_ids
+                .and_then(|ids| serde_json::from_str::<Option<Vec<i32>>>(&ids).ok().flatten())
+                .unwrap_or_default();
+            Ok(CombinedDiscount {
+                combined_discount_id: row.get("combined_discount_id")?,
+                name: row.get("combined_discount_name")?,
+                total_discount_percentage: row.get("total_discount_percentage")?,
+                discounts: discount_ids.iter()
+                    .map(|id| discounts.get(id).cloned().unwrap_or(DiscountInfo { discountid: *id, description: None, group: None, percentage: None }))
+                    .collect(),
+            })
+        })?;
+
+        let mut combined_discounts = Vec::new();
+        for combined_discount in combined_iter {
+            combined_discounts.push(combined_discount?);
+        }
+        Ok(combined_discounts)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting combined discounts failed: {}", e)))?;
+
+    Ok(combined_discounts)
+}
+
+
+// The base price with the most units wins when a package sold at several prices on the same day
+// Sorted by package, currency, country and date
+pub async fn get_daily_base_prices(connection: &Connection, from_date: String) -> Result<Vec<DailyPrice>, ErrorType> {
+    let prices = connection.call(move |conn| {
+        let mut stmt = conn.prepare("
+            SELECT packageid, currency, COALESCE(country_code, '') AS country_code, date, base_price, SUM(gross_units_sold) AS units
+            FROM steam_results
+            WHERE packageid IS NOT NULL AND currency IS NOT NULL AND currency != '' AND base_price > 0 AND date >= ?1
+            GROUP BY packageid, currency, COALESCE(country_code, ''), date, base_price
+            ORDER BY packageid, currency, country_code, date, units DESC
+        ")?;
+        let prices_iter = stmt.query_map(params![from_date], |row| {
+            Ok(DailyPrice {
+                packageid: row.get("packageid")?,
+                currency: row.get("currency")?,
+                country_code: row.get("country_code")?,
+                date: row.get("date")?,
+                base_price: row.get("base_price")?,
+            })
+        })?;
+
+        let mut prices: Vec<DailyPrice> = Vec::new();
+        for price in prices_iter {
+            let price = price?;
+            if prices.last().is_some_and(|p| p.packageid == price.packageid && p.currency == price.currency && p.country_code == price.country_code && p.date == price.date) {
+                continue;
+            }
+            prices.push(price);
+        }
+        Ok(prices)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting daily base prices failed: {}", e)))?;
+
+    Ok(prices)
+}
+
+
+// Periods reaching `from_date` are replaced, a rebuilt period may also replace the last period before it
+pub async fn replace_price_history(connection: &Connection, from_date: String, history: Vec<PricePeriod>) -> Result<usize, ErrorType> {
+    let count = connection.call(move |conn| {
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM price_history WHERE to_date >= ?1", params![from_date])?;
+        for period in history.iter() {
+            tx.execute(
+                "INSERT OR REPLACE INTO price_history (packageid, currency, country_code, from_date, to_date, base_price) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![period.packageid, period.currency, period.country_code, period.from_date, period.to_date, period.base_price],
+            )?;
+        }
+        tx.commit()?;
+        Ok(history.len())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving price history failed: {}", e)))?;
+
+    Ok(count)
+}
+
+
+// Sorted by package, currency, country and date
+pub async fn get_price_history(connection: &Connection, packageid: Option<i64>) -> Result<Vec<PricePeriod>, ErrorType> {
+    let history = connection.call(move |conn| {
+        let mut stmt = conn.prepare("
+            SELECT price_history.*, steam_package_info.package_name
+            FROM price_history
+            LEFT JOIN steam_package_info ON steam_package_info.packageid = price_history.packageid
+            WHERE ?1 IS NULL OR price_history.packageid = ?1
+            ORDER BY price_history.packageid, currency, country_code, from_date
+        ")?;
+        let history_iter = stmt.query_map(params![packageid], |row| {
+            Ok(PricePeriod {
+                packageid: row.get("packageid")?,
+                package_name: row.get("package_name")?,
+                currency: row.get("currency")?,
+                country_code: row.get("country_code")?,
+                from_date: row.get("from_date")?,
+                to_date: row.get("to_date")?,
+                base_price: row.get("base_price")?,
+            })
+        })?;
+
+        let mut history = Vec::new();
+        for period in history_iter {
+            history.push(period?);
+        }
+        Ok(history)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting price history failed: {}", e)))?;
+
+    Ok(history)
+}
+
+
+pub async fn get_reference_prices(connection: &Connection) -> Result<Vec<ReferencePrice>, ErrorType> {
+    let prices = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT * FROM reference_prices ORDER BY packageid, currency")?;
+        let prices_iter = stmt.query_map([], |row| {
+            Ok(ReferencePrice {
+                id: row.get("id")?,
+                packageid: row.get("packageid")?,
+                currency: row.get("currency")?,
+                price: row.get("price")?,
+            })
+        })?;
+
+        let mut prices = Vec::new();
+        for price in prices_iter {
+            prices.push(price?);
+        }
+        Ok(prices)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting reference prices failed: {}", e)))?;
+
+    Ok(prices)
+}
+
+
+// The imported matrix replaces the previous one
+pub async fn replace_reference_prices(connection: &Connection, prices: Vec<ReferencePrice>) -> Result<usize, ErrorType> {
+    let count = connection.call(move |conn| {
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM reference_prices", params![])?;
+        for price in prices.iter() {
+            tx.execute(
+                "INSERT INTO reference_prices (packageid, currency, price) VALUES (?1, ?2, ?3)",
+                params![price.packageid, price.currency, price.price],
+            )?;
+        }
+        tx.commit()?;
+        Ok(prices.len())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving reference prices failed: {}", e)))?;
+
+    Ok(count)
+}
+
+
+pub async fn get_bundle_rules(connection: &Connection) -> Result<Vec<BundleRule>, ErrorType> {
+    let rules = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT * FROM bundle_rules ORDER BY bundleid")?;
+        let rules_iter = stmt.query_map([], |row| {
+            let members: String = row.get("members")?;
+            Ok(BundleRule {
+                id: row.get("id")?,
+                bundleid: row.get("bundleid")?,
+                method: enum_from_text(row, "method")?,
+                members: serde_json::from_str(&members).unwrap_or_default(),
+            })
+        })?;
+
+        let mut rules = Vec::new();
+        for rule in rules_iter {
+            rules.push(rule?);
+        }
+        Ok(rules)
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting bundle rules failed: {}", e)))?;
+
+    Ok(rules)
+}
+
+
+pub async fn save_bundle_rule(connection: &Connection, rule: BundleRule) -> Result<i64, ErrorType> {
+    let members = serde_json::to_string(&rule.members)
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid bundle members: {}", e)))?;
+    let id = connection.call(move |conn| {
+        conn.execute(
+            "
+                INSERT INTO bundle_rules (
+                    id,
+                    bundleid,
+                    method,
+                    members
+                )
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (id) DO
+                UPDATE SET
+                    bundleid = ?2,
+                    method = ?3,
+                    members = ?4
+            ",
+            params![
+                rule.id,
+                rule.bundleid,
+                enum_to_text(&rule.method),
+                members,
+            ]
+        )?;
+        Ok(rule.id.unwrap_or(conn.last_insert_rowid()))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("saving bundle rule failed: {}", e)))?;
+
+    Ok(id)
+}
+
+
+pub async fn delete_bundle_rule(connection: &Connection, id: i64) -> Result<(), ErrorType> {
+    connection.call(move |conn| {
+        conn.execute("DELETE FROM bundle_rules WHERE id = ?1", params![id])?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("deleting bundle rule failed: {}", e)))?;
+
+    Ok(())
+}
+
+
+// Names of the apps and of the packages, by id
+pub async fn get_product_names(connection: &Connection) -> Result<(HashMap<i64, String>, HashMap<i64, String>), ErrorType> {
+    let names = connection.call(|conn| {
+        let mut stmt = conn.prepare("SELECT appid, app_name FROM steam_app_info WHERE app_name IS NOT NULL")?;
+        let apps = stmt.query_map([], |row| Ok((row.get("appid")?, row.get("app_name")?)))?
+            .collect::<Result<HashMap<i64, String>, _>>()?;
+        let mut stmt = conn.prepare("SELECT packageid, package_name FROM steam_package_info WHERE package_name IS NOT NULL")?;
+        let packages = stmt.query_map([], |row| Ok((row.get("packageid")?, row.get("package_name")?)))?
+            .collect::<Result<HashMap<i64, String>, _>>()?;
+        Ok((apps, packages))
+    })
+    .await
+    .map_err(|e| ErrorType::BadRequest(format!("getting product names failed: {}", e)))?;
+
+    Ok(names)
 }
@@ -0,0 +1,229 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::report::{self, format_count, format_usd};
+use crate::schedule::{self, RelativeRange, ScheduleFrequency};
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Local, NaiveDate, Timelike};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Serialize, Deserialize};
+use std::fmt::Write;
+use tokio_rusqlite::Connection;
+
+const TOP_COUNTRIES: usize = 5;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    // Plain connection upgraded with STARTTLS, usually port 587
+    StartTls,
+    // TLS from the start, usually port 465
+    Tls,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DigestSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: String,
+    // Encrypted with the keyring password in the database, like the Steam API key
+    pub password: String,
+    pub sender: String,
+    pub recipients: Vec<String>,
+    // A daily digest covers yesterday, a weekly one the previous week, a monthly one the previous month
+    pub frequency: ScheduleFrequency,
+    // Local hour from which the digest is sent, Steam needs a few hours to close the previous day
+    pub hour: u32,
+    // `yyyy/MM/dd` date of the last digest sent
+    pub last_sent: Option<String>,
+}
+
+impl Default for DigestSettings {
+    fn default() -> Self {
+        DigestSettings {
+            enabled: false,
+            host: String::new(),
+            port: 587,
+            security: SmtpSecurity::StartTls,
+            username: String::new(),
+            password: String::new(),
+            sender: String::new(),
+            recipients: Vec::new(),
+            frequency: ScheduleFrequency::Daily,
+            hour: 9,
+            last_sent: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Digest {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+
+// Sends the digest once per day, week or month, after the configured hour
+pub async fn send_due_digest(connection: &Connection) -> Result<(), ErrorType> {
+    let settings = database::get_encrypted_digest_settings(connection).await?;
+    let now = Local::now();
+    let today = now.date_naive();
+    if !settings.enabled || now.hour() < settings.hour || !schedule::is_due(settings.frequency, settings.last_sent.as_deref(), today) {
+        return Ok(());
+    }
+    let settings = DigestSettings { password: smtp_password(&settings.password).await?, ..settings };
+
+    let result = send_digest(connection, &settings, today).await?;
+    database::set_digest_last_sent(connection, app::format_date(today)).await?;
+    log::info!("{}", result);
+    Ok(())
+}
+
+
+// The headless daemon has no keyring, STEAMBOARD_SMTP_PASSWORD is used instead like STEAMBOARD_API_KEY
+async fn smtp_password(encrypted: &str) -> Result<String, ErrorType> {
+    if let Ok(password) = std::env::var("STEAMBOARD_SMTP_PASSWORD") {
+        return Ok(password);
+    }
+    if encrypted.is_empty() {
+        return Ok(String::new());
+    }
+    let password = app::get_password().await?;
+    app::decrypt(&password, encrypted)
+}
+
+
+pub async fn send_digest(connection: &Connection, settings: &DigestSettings, today: NaiveDate) -> Result<String, ErrorType> {
+    let digest = build_digest(connection, settings.frequency, today).await?;
+    deliver(settings, digest).await?;
+    Ok(format!("Digest sent to {}", settings.recipients.join(", ")))
+}
+
+
+pub async fn build_digest(connection: &Connection, frequency: ScheduleFrequency, today: NaiveDate) -> Result<Digest, ErrorType> {
+    let range = match frequency {
+        ScheduleFrequency::Daily => RelativeRange::PreviousDay,
+        ScheduleFrequency::Weekly => RelativeRange::PreviousWeek,
+        ScheduleFrequency::Monthly => RelativeRange::PreviousMonth,
+    };
+    let (from, to) = schedule::resolve_range(range, today);
+    let (from, to) = (from.map(app::format_date), to.map(app::format_date));
+    let rows = database::get_sale_details_by_date(connection, from.clone(), to.clone()).await?;
+
+    let period = match (from, to) {
+        (Some(from), Some(to)) if from != to => format!("{} - {}", from, to),
+        (Some(from), _) => from,
+        _ => "all time".into(),
+    };
+    let totals = summary::total(&rows);
+    let mut countries = summary::summarize(&rows, GroupBy::Country);
+    countries.truncate(TOP_COUNTRIES);
+
+    Ok(Digest {
+        subject: format!("Steamboard digest {}: {} units, {}", period, format_count(totals.net_units_sold), format_usd(totals.net_sales_usd)),
+        text: render_text(&period, &totals, &countries),
+        html: render_html(&period, &totals, &countries),
+    })
+}
+
+
+fn kpis(totals: &SummaryRow) -> Vec<(&'static str, String)> {
+    vec![
+        ("Net units", format_count(totals.net_units_sold)),
+        ("Net revenue", format_usd(totals.net_sales_usd)),
+        ("Gross revenue", format_usd(totals.gross_sales_usd)),
+        ("Units refunded", format_count(totals.gross_units_returned)),
+        ("Refunds", format_usd(totals.gross_returns_usd)),
+        ("Refund rate", format!("{:.1}%", totals.refund_rate() * 100.0)),
+    ]
+}
+
+
+fn country_label(row: &SummaryRow) -> String {
+    row.label.clone().unwrap_or(row.key.clone())
+}
+
+
+fn render_text(period: &str, totals: &SummaryRow, countries: &[SummaryRow]) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "Steamboard digest - {}\n", period);
+    for (label, value) in kpis(totals) {
+        let _ = writeln!(text, "{:<16}{}", label, value);
+    }
+    let _ = writeln!(text, "\nTop countries");
+    if countries.is_empty() {
+        let _ = writeln!(text, "No sales");
+    }
+    for country in countries.iter() {
+        let _ = writeln!(text, "{:<24}{:>8} units  {}", country_label(country), format_count(country.net_units_sold), format_usd(country.net_sales_usd));
+    }
+    text
+}
+
+
+fn render_html(period: &str, totals: &SummaryRow, countries: &[SummaryRow]) -> String {
+    let mut html = String::new();
+    let _ = write!(html, "<div style=\"font-family:sans-serif;color:#212529\"><h2>Steamboard digest - {}</h2><table cellpadding=\"4\">", report::escape_html(period));
+    for (label, value) in kpis(totals) {
+        let _ = write!(html, "<tr><td style=\"color:#788088\">{}</td><td><b>{}</b></td></tr>", label, value);
+    }
+    let _ = write!(html, "</table><h3>Top countries</h3>");
+    if countries.is_empty() {
+        let _ = write!(html, "<p>No sales</p>");
+    } else {
+        let _ = write!(html, "<table cellpadding=\"4\"><tr><th align=\"left\">Country</th><th align=\"right\">Net units</th><th align=\"right\">Net revenue</th></tr>");
+        for country in countries.iter() {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td align=\"right\">{}</td><td align=\"right\">{}</td></tr>",
+                report::escape_html(&country_label(country)),
+                format_count(country.net_units_sold),
+                format_usd(country.net_sales_usd)
+            );
+        }
+        let _ = write!(html, "</table>");
+    }
+    html.push_str("</div>");
+    html
+}
+
+
+async fn deliver(settings: &DigestSettings, digest: Digest) -> Result<(), ErrorType> {
+    if settings.host.is_empty() || settings.recipients.is_empty() {
+        return Err(ErrorType::Missing("The SMTP server and at least one recipient are needed".into()));
+    }
+
+    let parse_mailbox = |address: &str| address.trim().parse::<Mailbox>()
+        .map_err(|e| ErrorType::BadFormatting(format!("Invalid email address \"{}\": {}", address, e)));
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&settings.sender)?)
+        .subject(digest.subject);
+    for recipient in settings.recipients.iter() {
+        builder = builder.to(parse_mailbox(recipient)?);
+    }
+    let message = builder.multipart(MultiPart::alternative_plain_html(digest.text, digest.html))
+        .map_err(|e| ErrorType::BadFormatting(format!("Could not build the email: {}", e)))?;
+
+    let transport = match settings.security {
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host),
+        SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host),
+        SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)),
+    }
+    .map_err(|e| ErrorType::BadHttpRequest(format!("Invalid SMTP server: {}", e)))?
+    .port(settings.port);
+    let transport = if settings.username.is_empty() {
+        transport
+    } else {
+        transport.credentials(Credentials::new(settings.username.clone(), settings.password.clone()))
+    };
+
+    transport.build().send(message).await
+        .map_err(|e| ErrorType::BadHttpRequest(format!("Sending the digest failed: {}", e)))?;
+    Ok(())
+}
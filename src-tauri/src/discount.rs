@@ -0,0 +1,235 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use crate::summary::SummaryRow;
+use chrono::{Duration, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tokio_rusqlite::Connection;
+
+// Full price days before a run used as its baseline
+const BASELINE_DAYS: i64 = 28;
+// Days after a run checked for a post-sale hangover
+const HANGOVER_DAYS: i64 = 14;
+// A discounted day without a single sale has no row, runs survive that many of them in a row
+const MAX_QUIET_DAYS: i64 = 2;
+// Runs that started before the range still need their whole baseline
+const LOOKBACK_DAYS: i64 = 60 + BASELINE_DAYS;
+
+
+// One of the discounts stacked in a combined discount
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiscountInfo {
+    pub discountid: i32,
+    pub description: Option<String>,
+    pub group: Option<String>,
+    pub percentage: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CombinedDiscount {
+    pub combined_discount_id: i32,
+    pub name: Option<String>,
+    pub total_discount_percentage: Option<i32>,
+    pub discounts: Vec<DiscountInfo>,
+}
+
+// Consecutive days an app sold under the same combined discount
+#[derive(Serialize, Clone, Debug)]
+pub struct DiscountRun {
+    pub appid: String,
+    pub app_name: Option<String>,
+    pub combined_discount_id: i32,
+    pub name: Option<String>,
+    // Total discount percentage
+    pub depth: Option<i32>,
+    pub discounts: Vec<DiscountInfo>,
+    pub from_date: String,
+    pub to_date: String,
+    pub days: i64,
+    pub units: i64,
+    pub sales_usd: f64,
+    pub units_per_day: f64,
+    pub sales_usd_per_day: f64,
+    // Full price sales of the app, days spent in other runs are left out
+    pub baseline_days: i64,
+    pub baseline_units_per_day: f64,
+    pub baseline_sales_usd_per_day: f64,
+    // Run per day vs baseline per day, 0.5 is 50% more. None without baseline sales
+    pub units_uplift: Option<f64>,
+    pub sales_uplift: Option<f64>,
+    // Full price sales right after the run, up to the last synced day
+    pub hangover_days: i64,
+    pub hangover_units_per_day: f64,
+    pub hangover_sales_usd_per_day: f64,
+    // Hangover per day vs baseline per day, usually negative after a big sale
+    pub hangover_units_change: Option<f64>,
+    pub hangover_sales_change: Option<f64>,
+}
+
+// Sales of an app on a day, split by discount
+#[derive(Default)]
+struct Day {
+    full_price: SummaryRow,
+    discounted: BTreeMap<i32, SummaryRow>,
+}
+
+struct App {
+    label: Option<String>,
+    days: BTreeMap<NaiveDate, Day>,
+}
+
+
+// Runs overlapping the range, newest first
+pub async fn get_runs(connection: &Connection, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<Vec<DiscountRun>, ErrorType> {
+    let rows = database::get_sale_details_by_date(
+        connection,
+        from.map(|d| app::format_date(d - Duration::days(LOOKBACK_DAYS))),
+        to.map(|d| app::format_date(d + Duration::days(HANGOVER_DAYS))),
+    ).await?;
+    let Some(last_sale) = rows.iter().map(|r| r.date.as_str()).max().and_then(|d| app::parse_date(d).ok()) else {
+        return Ok(Vec::new());
+    };
+    let discounts: BTreeMap<i32, CombinedDiscount> = database::get_combined_discounts(connection).await?
+        .into_iter()
+        .map(|d| (d.combined_discount_id, d))
+        .collect();
+    // Depth and name seen on the sales rows, for discounts missing from the discount info
+    let mut row_discounts: BTreeMap<i32, (Option<i32>, Option<String>)> = BTreeMap::new();
+    for row in rows.iter() {
+        if let Some(id) = row.combined_discount_id {
+            row_discounts.entry(id).or_insert_with(|| (row.total_discount_percentage, row.combined_discount_name.clone()));
+        }
+    }
+
+    let mut runs = Vec::new();
+    for (appid, app) in group_by_app(&rows).into_iter() {
+        let app_runs = find_runs(&app);
+        let discounted_days: BTreeSet<NaiveDate> = app_runs.iter()
+            .flat_map(|(_, start, end)| start.iter_days().take_while(move |d| d <= end))
+            .collect();
+
+        for (id, start, end) in app_runs.into_iter() {
+            if from.is_some_and(|from| end < from) || to.is_some_and(|to| start > to) {
+                continue;
+            }
+            let mut during = SummaryRow::default();
+            for day in app.days.range(start..=end).filter_map(|(_, d)| d.discounted.get(&id)) {
+                during.merge(day);
+            }
+            let baseline = full_price(&app, &discounted_days, start - Duration::days(BASELINE_DAYS), start - Duration::days(1));
+            let hangover = full_price(&app, &discounted_days, end + Duration::days(1), (end + Duration::days(HANGOVER_DAYS)).min(last_sale));
+
+            let combined = discounts.get(&id);
+            let from_rows = row_discounts.get(&id);
+            let depth = combined.and_then(|d| d.total_discount_percentage)
+                .or_else(|| from_rows.and_then(|d| d.0));
+            let name = combined.and_then(|d| d.name.clone())
+                .or_else(|| from_rows.and_then(|d| d.1.clone()));
+
+            let days = (end - start).num_days() + 1;
+            let per_day = |total: f64, days: i64| if days > 0 { total / days as f64 } else { 0.0 };
+            let change = |value: f64, before: f64| if before > 0.0 { Some(value / before - 1.0) } else { None };
+
+            let units_per_day = per_day(during.net_units_sold as f64, days);
+            let sales_usd_per_day = per_day(during.net_sales_usd, days);
+            let baseline_units_per_day = per_day(baseline.1.net_units_sold as f64, baseline.0);
+            let baseline_sales_usd_per_day = per_day(baseline.1.net_sales_usd, baseline.0);
+            let hangover_units_per_day = per_day(hangover.1.net_units_sold as f64, hangover.0);
+            let hangover_sales_usd_per_day = per_day(hangover.1.net_sales_usd, hangover.0);
+
+            runs.push(DiscountRun {
+                appid: appid.clone(),
+                app_name: app.label.clone(),
+                combined_discount_id: id,
+                name,
+                depth,
+                discounts: combined.map(|d| d.discounts.clone()).unwrap_or_default(),
+                from_date: app::format_date(start),
+                to_date: app::format_date(end),
+                days,
+                units: during.net_units_sold,
+                sales_usd: during.net_sales_usd,
+                units_per_day,
+                sales_usd_per_day,
+                baseline_days: baseline.0,
+                baseline_units_per_day,
+                baseline_sales_usd_per_day,
+                units_uplift: change(units_per_day, baseline_units_per_day),
+                sales_uplift: change(sales_usd_per_day, baseline_sales_usd_per_day),
+                hangover_days: hangover.0,
+                hangover_units_per_day,
+                hangover_sales_usd_per_day,
+                hangover_units_change: if hangover.0 > 0 { change(hangover_units_per_day, baseline_units_per_day) } else { None },
+                hangover_sales_change: if hangover.0 > 0 { change(hangover_sales_usd_per_day, baseline_sales_usd_per_day) } else { None },
+            });
+        }
+    }
+
+    runs.sort_by(|a, b| b.from_date.cmp(&a.from_date).then_with(|| a.appid.cmp(&b.appid)));
+    Ok(runs)
+}
+
+
+fn group_by_app(rows: &[CPartnerFinancialsDetailedSalesResult]) -> BTreeMap<String, App> {
+    let mut apps: BTreeMap<String, App> = BTreeMap::new();
+    for row in rows.iter() {
+        let Some(appid) = row.appid.or(row.primary_appid) else {
+            continue;
+        };
+        let Ok(date) = app::parse_date(&row.date) else {
+            continue;
+        };
+        let app = apps.entry(appid.to_string()).or_insert_with(|| App { label: None, days: BTreeMap::new() });
+        if app.label.is_none() {
+            app.label = row.app_name.clone();
+        }
+        let day = app.days.entry(date).or_default();
+        match row.combined_discount_id {
+            Some(id) if id != 0 => day.discounted.entry(id).or_default().add(row),
+            _ => day.full_price.add(row),
+        }
+    }
+    apps
+}
+
+
+// (combined discount, first day, last day) of every run of the app
+fn find_runs(app: &App) -> Vec<(i32, NaiveDate, NaiveDate)> {
+    let mut open: BTreeMap<i32, (NaiveDate, NaiveDate)> = BTreeMap::new();
+    let mut runs = Vec::new();
+    for (date, day) in app.days.iter() {
+        for id in day.discounted.keys() {
+            match open.get_mut(id) {
+                Some((_, end)) if (*date - *end).num_days() <= MAX_QUIET_DAYS + 1 => *end = *date,
+                Some(run) => {
+                    runs.push((*id, run.0, run.1));
+                    *run = (*date, *date);
+                }
+                None => {
+                    open.insert(*id, (*date, *date));
+                }
+            }
+        }
+    }
+    runs.extend(open.into_iter().map(|(id, (start, end))| (id, start, end)));
+    runs
+}
+
+
+// Number of full price days in the range and their sales, days without sales count as zero once the app sold
+fn full_price(app: &App, discounted_days: &BTreeSet<NaiveDate>, from: NaiveDate, to: NaiveDate) -> (i64, SummaryRow) {
+    let from = app.days.keys().next().map_or(from, |first| from.max(*first));
+    let mut total = SummaryRow::default();
+    let mut days = 0;
+    for date in from.iter_days().take_while(|d| *d <= to) {
+        if discounted_days.contains(&date) {
+            continue;
+        }
+        days += 1;
+        if let Some(day) = app.days.get(&date) {
+            total.merge(&day.full_price);
+        }
+    }
+    (days, total)
+}
@@ -0,0 +1,466 @@
+use crate::app::{self, ErrorType};
+use crate::bundle;
+use crate::database;
+use crate::forecast;
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use crate::workbook;
+use arrow::array::{Array, ArrayRef, AsArray, Date32Array, Float64Array, Int32Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Date32Type, Field, Float64Type, Int32Type, Int64Type, Schema};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Duration, NaiveDate};
+use flate2::write::GzEncoder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::{Serialize, Deserialize};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::task;
+use tokio_rusqlite::Connection;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+type Row = CPartnerFinancialsDetailedSalesResult;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Ndjson,
+    Parquet,
+    Arrow,
+    Xlsx,
+    Ods,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportColumn {
+    // Field name of `CPartnerFinancialsDetailedSalesResult`
+    pub field: String,
+    // Header written instead of the field name
+    pub label: Option<String>,
+}
+
+// Saved selection of columns and formats, applied to CSV and clipboard exports
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportProfile {
+    pub id: Option<i64>,
+    pub name: String,
+    pub columns: Vec<ExportColumn>,
+    pub decimal_places: Option<usize>,
+    pub decimal_separator: String,
+    // chrono format string, `yyyy/MM/dd` dates are kept when not set
+    pub date_format: Option<String>,
+    pub null_value: String,
+}
+
+
+pub async fn export<P>(connection: &Connection, from_date: Option<String>, to_date: Option<String>, path: String, options: ExportOptions, on_progress: P) -> Result<String, ErrorType>
+where
+    P: Fn(f32) + Send + 'static,
+{
+    if options.profile.is_some() && !matches!(options.format, ExportFormat::Csv) {
+        return Err(ErrorType::BadRequest("Export profiles can only be used with CSV exports".into()));
+    }
+
+    let path_clone = path.clone();
+    let written = match options.format {
+        // Workbooks need every row for their summary sheets, they can't be streamed. They also get
+        // the current forecast, computed from the whole history and not the exported range
+        ExportFormat::Xlsx | ExportFormat::Ods => {
+            let annotations = database::get_annotations(connection, from_date.clone(), to_date.clone()).await?;
+            let rows = database::get_sale_details_by_date(connection, from_date, to_date).await?;
+            let forecasts = forecast::forecast_all(connection).await?;
+            let bundles = bundle::load(connection).await?;
+            task::spawn_blocking(move || -> Result<usize, ErrorType> {
+                let sheets = workbook::sheets(&rows, &bundles, &forecasts, &annotations)?;
+                let bytes = match options.format {
+                    ExportFormat::Xlsx => workbook::xlsx_bytes(&sheets)?,
+                    _ => workbook::ods_bytes(&sheets)?,
+                };
+                let mut output = Output::create(&path_clone, options.compression)?;
+                output.write_all(&bytes).map_err(write_error)?;
+                output.finish()?;
+                on_progress(1.0);
+                Ok(rows.len())
+            })
+            .await
+            .map_err(|e| ErrorType::BadFormatting(format!("Error while creating the export file: {}", e)))??
+        }
+        _ => {
+            let total = database::count_sale_details(connection, from_date.clone(), to_date.clone()).await?;
+            database::with_sale_details(connection, from_date, to_date, move |rows| {
+                let output = Output::create(&path_clone, options.compression)?;
+                let mut writer = SalesWriter::new(output, &options)?;
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                let mut written = 0;
+
+                for row in rows {
+                    chunk.push(row.map_err(|e| ErrorType::BadRequest(format!("getting sales details failed: {}", e)))?);
+                    if chunk.len() == CHUNK_SIZE {
+                        writer.write_chunk(&chunk)?;
+                        written += chunk.len();
+                        chunk.clear();
+                        on_progress(written as f32 / total.max(1) as f32);
+                    }
+                }
+
+                writer.write_chunk(&chunk)?;
+                written += chunk.len();
+                writer.finish()?;
+                on_progress(1.0);
+                Ok(written)
+            }).await?
+        }
+    };
+
+    Ok(format!("{} rows exported to {}", written, &path))
+}
+
+
+const CHUNK_SIZE: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zip,
+}
+
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub delimiter: u8,
+    pub profile: Option<ExportProfile>,
+    pub compression: Compression,
+}
+
+
+fn write_error(e: std::io::Error) -> ErrorType {
+    ErrorType::BadFormatting(format!("Error while writing to file: {}", e))
+}
+
+
+// Export file, optionally compressed on the fly
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zip(ZipWriter<BufWriter<File>>),
+}
+
+impl Output {
+    fn create(path: &str, compression: Compression) -> Result<Output, ErrorType> {
+        let file = File::create(Path::new(path)).map_err(|e| ErrorType::BadFormatting(format!("File error: {}", e)))?;
+        let writer = BufWriter::new(file);
+
+        match compression {
+            Compression::None => Ok(Output::Plain(writer)),
+            Compression::Gzip => Ok(Output::Gzip(GzEncoder::new(writer, flate2::Compression::default()))),
+            Compression::Zip => {
+                // The archive holds a single file, named after the archive itself
+                let file_name = Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or("export".into());
+                let entry_name = file_name.strip_suffix(".zip").unwrap_or(&file_name).to_string();
+                let mut zip = ZipWriter::new(writer);
+                zip.start_file(entry_name, SimpleFileOptions::default().large_file(true))
+                    .map_err(|e| ErrorType::BadFormatting(format!("Zip error: {}", e)))?;
+                Ok(Output::Zip(zip))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), ErrorType> {
+        let mut writer = match self {
+            Output::Plain(writer) => writer,
+            Output::Gzip(encoder) => encoder.finish().map_err(write_error)?,
+            Output::Zip(zip) => zip.finish().map_err(|e| ErrorType::BadFormatting(format!("Zip error: {}", e)))?,
+        };
+        writer.flush().map_err(write_error)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Zip(zip) => zip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Zip(zip) => zip.flush(),
+        }
+    }
+}
+
+
+// Writes the rows chunk by chunk in the requested format
+enum SalesWriter {
+    Csv(csv::Writer<Output>),
+    CsvProfile { writer: csv::Writer<Output>, profile: ExportProfile, has_header: bool },
+    Json { output: Output, first: bool },
+    Ndjson(Output),
+    Parquet(ArrowWriter<Output>),
+    Arrow(FileWriter<Output>),
+}
+
+impl SalesWriter {
+    fn new(mut output: Output, options: &ExportOptions) -> Result<SalesWriter, ErrorType> {
+        let csv_writer = |output: Output| csv::WriterBuilder::new().delimiter(options.delimiter).from_writer(output);
+        let schema = record_batch(&[])?.schema();
+
+        let writer = match (options.format, &options.profile) {
+            (ExportFormat::Csv, Some(profile)) => SalesWriter::CsvProfile { writer: csv_writer(output), profile: profile.clone(), has_header: false },
+            (ExportFormat::Csv, None) => SalesWriter::Csv(csv_writer(output)),
+            (ExportFormat::Json, _) => {
+                output.write_all(b"[").map_err(write_error)?;
+                SalesWriter::Json { output, first: true }
+            }
+            (ExportFormat::Ndjson, _) => SalesWriter::Ndjson(output),
+            (ExportFormat::Parquet, _) => {
+                // Row groups are buffered until full, keep them at one chunk
+                let properties = WriterProperties::builder().set_max_row_group_size(CHUNK_SIZE).build();
+                SalesWriter::Parquet(
+                    ArrowWriter::try_new(output, schema, Some(properties)).map_err(|e| ErrorType::BadFormatting(format!("Parquet error: {}", e)))?
+                )
+            }
+            (ExportFormat::Arrow, _) => SalesWriter::Arrow(
+                FileWriter::try_new(output, &schema).map_err(|e| ErrorType::BadFormatting(format!("Arrow error: {}", e)))?
+            ),
+            (format, _) => return Err(ErrorType::BadRequest(format!("{:?} exports can't be streamed", format))),
+        };
+
+        Ok(writer)
+    }
+
+    fn write_chunk(&mut self, rows: &[Row]) -> Result<(), ErrorType> {
+        let csv_error = |e: csv::Error| ErrorType::BadFormatting(format!("CSV serialize error: {}", e));
+        let json_error = |e: serde_json::Error| ErrorType::BadFormatting(format!("JSON serialize error: {}", e));
+
+        match self {
+            SalesWriter::Csv(writer) => {
+                for row in rows.iter() {
+                    writer.serialize(row).map_err(csv_error)?;
+                }
+            }
+            SalesWriter::CsvProfile { writer, profile, has_header } => {
+                // Every chunk comes with the headers, only the first ones are kept
+                let records = apply_profile(rows, profile)?;
+                let skip = if *has_header { 1 } else { 0 };
+                for record in records.iter().skip(skip) {
+                    writer.write_record(record).map_err(csv_error)?;
+                }
+                *has_header = true;
+            }
+            SalesWriter::Json { output, first } => {
+                for row in rows.iter() {
+                    output.write_all(if *first { b"\n" } else { b",\n" }).map_err(write_error)?;
+                    serde_json::to_writer(&mut *output, row).map_err(json_error)?;
+                    *first = false;
+                }
+            }
+            SalesWriter::Ndjson(output) => {
+                for row in rows.iter() {
+                    serde_json::to_writer(&mut *output, row).map_err(json_error)?;
+                    output.write_all(b"\n").map_err(write_error)?;
+                }
+            }
+            SalesWriter::Parquet(writer) => {
+                if !rows.is_empty() {
+                    writer.write(&record_batch(rows)?).map_err(|e| ErrorType::BadFormatting(format!("Parquet error: {}", e)))?;
+                }
+            }
+            SalesWriter::Arrow(writer) => {
+                if !rows.is_empty() {
+                    writer.write(&record_batch(rows)?).map_err(|e| ErrorType::BadFormatting(format!("Arrow error: {}", e)))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ErrorType> {
+        let csv_error = |e: csv::IntoInnerError<csv::Writer<Output>>| ErrorType::BadFormatting(format!("CSV flush error: {}", e));
+
+        let output = match self {
+            SalesWriter::Csv(writer) => writer.into_inner().map_err(csv_error)?,
+            SalesWriter::CsvProfile { writer, .. } => writer.into_inner().map_err(csv_error)?,
+            SalesWriter::Json { mut output, .. } => {
+                output.write_all(b"\n]\n").map_err(write_error)?;
+                output
+            }
+            SalesWriter::Ndjson(output) => output,
+            SalesWriter::Parquet(mut writer) => {
+                writer.finish().map_err(|e| ErrorType::BadFormatting(format!("Parquet error: {}", e)))?;
+                writer.into_inner().map_err(|e| ErrorType::BadFormatting(format!("Parquet error: {}", e)))?
+            }
+            SalesWriter::Arrow(writer) => writer.into_inner().map_err(|e| ErrorType::BadFormatting(format!("Arrow error: {}", e)))?,
+        };
+
+        output.finish()
+    }
+}
+
+
+// Typed, nullable columns so Option fields stay null instead of being guessed from text
+pub fn record_batch(rows: &[Row]) -> Result<RecordBatch, ErrorType> {
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("partnerid", int32_column(rows, |r| Some(r.partnerid))),
+        ("date", date_column(rows)),
+        ("line_item_type", string_column(rows, |r| r.line_item_type.as_deref())),
+        ("packageid", int32_column(rows, |r| r.packageid)),
+        ("bundleid", int32_column(rows, |r| r.bundleid)),
+        ("appid", int32_column(rows, |r| r.appid)),
+        ("game_item_id", int32_column(rows, |r| r.game_item_id)),
+        ("package_sale_type", string_column(rows, |r| r.package_sale_type.as_deref())),
+        ("key_request_id", int32_column(rows, |r| r.key_request_id)),
+        ("platform", string_column(rows, |r| r.platform.as_deref())),
+        ("country_code", string_column(rows, |r| r.country_code.as_deref())),
+        ("base_price", int64_column(rows, |r| r.base_price)),
+        ("sale_price", int64_column(rows, |r| r.sale_price)),
+        ("currency", string_column(rows, |r| r.currency.as_deref())),
+        ("gross_units_sold", int32_column(rows, |r| r.gross_units_sold)),
+        ("gross_units_returned", int32_column(rows, |r| r.gross_units_returned)),
+        ("gross_sales_usd", float64_column(rows, |r| r.gross_sales_usd)),
+        ("gross_returns_usd", float64_column(rows, |r| r.gross_returns_usd)),
+        ("net_tax_usd", float64_column(rows, |r| r.net_tax_usd)),
+        ("gross_units_activated", int32_column(rows, |r| r.gross_units_activated)),
+        ("view_grant_partnerid", int32_column(rows, |r| r.view_grant_partnerid)),
+        ("net_units_sold", int32_column(rows, |r| r.net_units_sold)),
+        ("net_sales_usd", float64_column(rows, |r| r.net_sales_usd)),
+        ("avg_sale_price_usd", float64_column(rows, |r| r.avg_sale_price_usd)),
+        ("combined_discount_id", int32_column(rows, |r| r.combined_discount_id)),
+        ("primary_appid", int32_column(rows, |r| r.primary_appid)),
+        ("additional_revenue_share_tier", int32_column(rows, |r| r.additional_revenue_share_tier)),
+        ("partner_name", string_column(rows, |r| r.partner_name.as_deref())),
+        ("package_name", string_column(rows, |r| r.package_name.as_deref())),
+        ("bundle_name", string_column(rows, |r| r.bundle_name.as_deref())),
+        ("app_name", string_column(rows, |r| r.app_name.as_deref())),
+        ("game_item_description", string_column(rows, |r| r.game_item_description.as_deref())),
+        ("game_item_category", string_column(rows, |r| r.game_item_category.as_deref())),
+        ("key_request_notes", string_column(rows, |r| r.key_request_notes.as_deref())),
+        ("game_code_id", int32_column(rows, |r| r.game_code_id)),
+        ("game_code_description", string_column(rows, |r| r.game_code_description.as_deref())),
+        ("territory_code_id", int32_column(rows, |r| r.territory_code_id)),
+        ("territory_code_description", string_column(rows, |r| r.territory_code_description.as_deref())),
+        ("country_name", string_column(rows, |r| r.country_name.as_deref())),
+        ("region", string_column(rows, |r| r.region.as_deref())),
+        ("combined_discount_name", string_column(rows, |r| r.combined_discount_name.as_deref())),
+        ("total_discount_percentage", int32_column(rows, |r| r.total_discount_percentage)),
+        ("extra", string_column(rows, |r| r.extra.as_deref())),
+    ];
+
+    let schema = Schema::new(
+        columns.iter()
+            .map(|(name, array)| Field::new(*name, array.data_type().clone(), true))
+            .collect::<Vec<Field>>()
+    );
+
+    RecordBatch::try_new(Arc::new(schema), columns.into_iter().map(|(_, array)| array).collect())
+        .map_err(|e| ErrorType::BadFormatting(format!("Arrow error: {}", e)))
+}
+
+
+fn int32_column(rows: &[Row], get: impl Fn(&Row) -> Option<i32>) -> ArrayRef {
+    Arc::new(rows.iter().map(get).collect::<Int32Array>())
+}
+
+
+fn int64_column(rows: &[Row], get: impl Fn(&Row) -> Option<i64>) -> ArrayRef {
+    Arc::new(rows.iter().map(get).collect::<Int64Array>())
+}
+
+
+// Widened through the shortest decimal representation, so 9.99 doesn't become 9.989999771118164
+fn float64_column(rows: &[Row], get: impl Fn(&Row) -> Option<f32>) -> ArrayRef {
+    Arc::new(rows.iter().map(|r| get(r).map(|v| v.to_string().parse().unwrap_or(v as f64))).collect::<Float64Array>())
+}
+
+
+fn string_column<'a>(rows: &'a [Row], get: impl Fn(&'a Row) -> Option<&'a str>) -> ArrayRef {
+    Arc::new(rows.iter().map(get).collect::<StringArray>())
+}
+
+
+// Days since the Unix epoch, as expected by the Arrow Date32 type
+fn date_column(rows: &[Row]) -> ArrayRef {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    Arc::new(
+        rows.iter()
+            .map(|r| app::parse_date(&r.date).ok().map(|date| (date - epoch).num_days() as i32))
+            .collect::<Date32Array>()
+    )
+}
+
+
+// Chrono panics when formatting with an invalid specifier, profiles are checked before they are saved
+pub fn validate_date_format(format: &str) -> Result<(), ErrorType> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(ErrorType::BadFormatting(format!("Invalid date format: {}", format)));
+    }
+    Ok(())
+}
+
+
+// Formatted records for the profile columns, the first record holds the headers
+pub fn apply_profile(rows: &[Row], profile: &ExportProfile) -> Result<Vec<Vec<String>>, ErrorType> {
+    let batch = record_batch(rows)?;
+    let schema = batch.schema();
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+
+    let mut columns = Vec::new();
+    for column in profile.columns.iter() {
+        let index = schema.index_of(&column.field)
+            .map_err(|_| ErrorType::BadRequest(format!("Unknown export column: {}", column.field)))?;
+        columns.push(batch.column(index));
+    }
+
+    let mut records = vec![
+        profile.columns.iter().map(|c| c.label.clone().unwrap_or(c.field.clone())).collect::<Vec<String>>()
+    ];
+
+    for i in 0..batch.num_rows() {
+        let record = columns.iter().map(|column| {
+            if column.is_null(i) {
+                return Ok(profile.null_value.clone());
+            }
+            let value = match column.data_type() {
+                DataType::Int32 => column.as_primitive::<Int32Type>().value(i).to_string(),
+                DataType::Int64 => column.as_primitive::<Int64Type>().value(i).to_string(),
+                DataType::Float64 => {
+                    let value = column.as_primitive::<Float64Type>().value(i);
+                    let formatted = match profile.decimal_places {
+                        Some(places) => format!("{:.*}", places, value),
+                        None => value.to_string(),
+                    };
+                    formatted.replace('.', &profile.decimal_separator)
+                }
+                DataType::Date32 => {
+                    let date = epoch + Duration::days(column.as_primitive::<Date32Type>().value(i) as i64);
+                    // Profiles saved before the format was checked may still hold a bad one
+                    let mut formatted = String::new();
+                    write!(formatted, "{}", date.format(profile.date_format.as_deref().unwrap_or(app::DATE_FORMAT)))
+                        .map_err(|_| ErrorType::BadFormatting(format!("Invalid date format: {}", profile.date_format.as_deref().unwrap_or_default())))?;
+                    formatted
+                }
+                DataType::Utf8 => column.as_string::<i32>().value(i).to_string(),
+                _ => profile.null_value.clone(),
+            };
+            Ok(value)
+        }).collect::<Result<Vec<String>, ErrorType>>()?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
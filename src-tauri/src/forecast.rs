@@ -0,0 +1,228 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Duration, Local, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use tokio_rusqlite::Connection;
+
+// Weekly seasonality, weekends sell differently than week days
+const SEASON: usize = 7;
+// Two full seasons are needed to initialize the model
+const MIN_HISTORY_DAYS: usize = 2 * SEASON;
+const HISTORY_DAYS: i64 = 365;
+pub const HORIZONS: [usize; 2] = [30, 90];
+// Damps the trend so a good month doesn't project to the moon after 90 days
+const PHI: f64 = 0.98;
+// 95% confidence bands
+const Z: f64 = 1.96;
+
+const ALPHAS: [f64; 6] = [0.05, 0.1, 0.2, 0.3, 0.5, 0.7];
+const BETAS: [f64; 5] = [0.0, 0.01, 0.05, 0.1, 0.2];
+const GAMMAS: [f64; 5] = [0.05, 0.1, 0.2, 0.3, 0.5];
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForecastMetric {
+    Units,
+    Revenue,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForecastPoint {
+    pub date: String,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+// Sum of the next `days` days
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Projection {
+    pub days: usize,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Forecast {
+    // Appid, or "total" for all apps together
+    pub key: String,
+    pub label: Option<String>,
+    pub metric: ForecastMetric,
+    pub history_days: usize,
+    pub points: Vec<ForecastPoint>,
+    pub projections: Vec<Projection>,
+}
+
+// Flat forecast line for CSV exports and spreadsheets
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForecastRow {
+    pub key: String,
+    pub label: Option<String>,
+    pub metric: ForecastMetric,
+    pub date: String,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+
+// Forecasts units and net revenue of every app and of the total, from the last complete day
+pub async fn forecast_all(connection: &Connection) -> Result<Vec<Forecast>, ErrorType> {
+    let last_day = Local::now().date_naive() - Duration::days(1);
+    let first_day = last_day - Duration::days(HISTORY_DAYS - 1);
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(first_day)), Some(app::format_date(last_day))).await?;
+
+    let mut groups = summary::daily_by_group(&rows, GroupBy::App);
+    let mut total: BTreeMap<String, SummaryRow> = BTreeMap::new();
+    for days in groups.values() {
+        for (date, day) in days.iter() {
+            total.entry(date.clone()).or_default().merge(day);
+        }
+    }
+    groups.insert("total".into(), total);
+
+    let mut forecasts = Vec::new();
+    for (key, days) in groups.iter() {
+        let label = if key == "total" { Some("Total".into()) } else { days.values().find_map(|d| d.label.clone()) };
+        for metric in [ForecastMetric::Units, ForecastMetric::Revenue] {
+            if let Some(forecast) = forecast_group(key, label.clone(), metric, days, last_day) {
+                forecasts.push(forecast);
+            }
+        }
+    }
+
+    Ok(forecasts)
+}
+
+
+pub fn to_rows(forecasts: &[Forecast]) -> Vec<ForecastRow> {
+    forecasts.iter().flat_map(|f| f.points.iter().map(|p| ForecastRow {
+        key: f.key.clone(),
+        label: f.label.clone(),
+        metric: f.metric,
+        date: p.date.clone(),
+        value: p.value,
+        lower: p.lower,
+        upper: p.upper,
+    })).collect()
+}
+
+
+fn forecast_group(key: &str, label: Option<String>, metric: ForecastMetric, days: &BTreeMap<String, SummaryRow>, last_day: NaiveDate) -> Option<Forecast> {
+    // Days without sales are zero, from the first sale on
+    let first_day = app::parse_date(days.keys().next()?).ok()?;
+    let series: Vec<f64> = first_day.iter_days()
+        .take_while(|d| *d <= last_day)
+        .map(|d| match (days.get(&app::format_date(d)), metric) {
+            (Some(day), ForecastMetric::Units) => day.net_units_sold as f64,
+            (Some(day), ForecastMetric::Revenue) => day.net_sales_usd,
+            (None, _) => 0.0,
+        })
+        .collect();
+    if series.len() < MIN_HISTORY_DAYS {
+        return None;
+    }
+
+    let horizon = HORIZONS.iter().copied().max().unwrap_or(0);
+    let model = fit(&series)?;
+    let points: Vec<ForecastPoint> = (1..=horizon).map(|h| {
+        let value = model.predict(h);
+        // Error variance of simple exponential smoothing, close enough for the seasonal model
+        let band = Z * model.sigma * (1.0 + (h - 1) as f64 * model.alpha.powi(2)).sqrt();
+        ForecastPoint {
+            date: app::format_date(last_day + Duration::days(h as i64)),
+            value: value.max(0.0),
+            lower: (value - band).max(0.0),
+            upper: (value + band).max(0.0),
+        }
+    }).collect();
+
+    // Summing the daily bands treats the errors of consecutive days as fully correlated, it's wide on purpose
+    let projections = HORIZONS.iter().map(|&days| Projection {
+        days,
+        value: points.iter().take(days).map(|p| p.value).sum(),
+        lower: points.iter().take(days).map(|p| p.lower).sum(),
+        upper: points.iter().take(days).map(|p| p.upper).sum(),
+    }).collect();
+
+    Some(Forecast {
+        key: key.to_string(),
+        label,
+        metric,
+        history_days: series.len(),
+        points,
+        projections,
+    })
+}
+
+
+// Additive Holt-Winters with a damped trend, state after the last observed day
+struct Model {
+    alpha: f64,
+    level: f64,
+    trend: f64,
+    // Seasonal components of the next `SEASON` days, in order
+    seasonals: Vec<f64>,
+    // Standard deviation of the one step ahead errors
+    sigma: f64,
+}
+
+impl Model {
+    fn predict(&self, h: usize) -> f64 {
+        let damping: f64 = (1..=h).map(|i| PHI.powi(i as i32)).sum();
+        self.level + damping * self.trend + self.seasonals[(h - 1) % SEASON]
+    }
+}
+
+
+// Picks the smoothing parameters with the lowest one step ahead squared error
+fn fit(series: &[f64]) -> Option<Model> {
+    let mut best: Option<(f64, Model)> = None;
+    for alpha in ALPHAS {
+        for beta in BETAS {
+            for gamma in GAMMAS {
+                let (sse, model) = smooth(series, alpha, beta, gamma);
+                if best.as_ref().is_none_or(|(best_sse, _)| sse < *best_sse) {
+                    best = Some((sse, model));
+                }
+            }
+        }
+    }
+    best.map(|(_, model)| model)
+}
+
+
+fn smooth(series: &[f64], alpha: f64, beta: f64, gamma: f64) -> (f64, Model) {
+    let first: f64 = series[..SEASON].iter().sum::<f64>() / SEASON as f64;
+    let second: f64 = series[SEASON..2 * SEASON].iter().sum::<f64>() / SEASON as f64;
+    let mut level = first;
+    let mut trend = (second - first) / SEASON as f64;
+    let mut seasonals: Vec<f64> = series[..SEASON].iter().map(|y| y - first).collect();
+
+    let mut sse = 0.0;
+    for (t, &y) in series.iter().enumerate().skip(SEASON) {
+        let season = seasonals[t % SEASON];
+        let error = y - (level + PHI * trend + season);
+        sse += error * error;
+
+        let previous_level = level;
+        level = alpha * (y - season) + (1.0 - alpha) * (previous_level + PHI * trend);
+        trend = beta * (level - previous_level) + (1.0 - beta) * PHI * trend;
+        seasonals[t % SEASON] = gamma * (y - level) + (1.0 - gamma) * season;
+    }
+
+    let errors = (series.len() - SEASON) as f64;
+    let next = series.len() % SEASON;
+    let model = Model {
+        alpha,
+        level,
+        trend,
+        seasonals: (0..SEASON).map(|i| seasonals[(next + i) % SEASON]).collect(),
+        sigma: (sse / errors).sqrt(),
+    };
+    (sse, model)
+}
@@ -0,0 +1,26 @@
+// Everything that doesn't depend on the UI, shared by the desktop app and `steamboard-cli`
+pub mod alert;
+pub mod annotation;
+pub mod anomaly;
+pub mod api;
+pub mod app;
+pub mod bundle;
+pub mod calendar;
+pub mod clipboard;
+pub mod database;
+pub mod digest;
+pub mod discount;
+pub mod export;
+pub mod forecast;
+pub mod metrics;
+pub mod notifier;
+pub mod pdf;
+pub mod pricing;
+pub mod report;
+pub mod royalty;
+pub mod schedule;
+pub mod steam;
+pub mod summary;
+pub mod target;
+pub mod webhook;
+pub mod workbook;
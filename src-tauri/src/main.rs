@@ -2,24 +2,24 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 //#![allow(dead_code, unused_variables)]
 
-mod app;
-mod database;
-mod steam;
-
 use dotenv::dotenv;
 use serde_json::Value;
 use std::env;
+use std::sync::Arc;
 use arboard::Clipboard;
 use tauri::{
     image::Image,
     menu::{Menu, MenuItem},
     tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
-    AppHandle, Manager
+    AppHandle, Emitter, Manager
 };
+use tauri_plugin_notification::NotificationExt;
 use tauri_plugin_opener::OpenerExt;
 
-use crate::app::SETTINGS;
-use crate::app::{ErrorType};
+use tauri_app_lib::{alert, annotation, anomaly, api, app, bundle, calendar, clipboard, database, digest, discount, export, forecast, pricing, report, royalty, schedule, steam, summary, target, webhook};
+use tauri_app_lib::app::SETTINGS;
+use tauri_app_lib::app::{ErrorType};
+use tauri_app_lib::notifier::Notifier;
 
 
 #[tokio::main]
@@ -57,7 +57,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             ))
             .max_file_size(50_000) /* bytes */
-            .filter(|metadata| metadata.target().starts_with("Steamboard"))
+            .filter(|metadata| metadata.target().starts_with("Steamboard") || metadata.target().starts_with("tauri_app_lib"))
             .build())
         .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_window_state::Builder::default().build())
@@ -148,6 +148,64 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             open_location_command,
             copy_to_clipboard_command,
             get_detailed_sales_command,
+            export_command,
+            get_export_profiles_command,
+            save_export_profile_command,
+            delete_export_profile_command,
+            get_export_schedules_command,
+            save_export_schedule_command,
+            delete_export_schedule_command,
+            run_export_schedule_command,
+            get_api_settings_command,
+            save_api_settings_command,
+            get_webhooks_command,
+            save_webhook_command,
+            delete_webhook_command,
+            test_webhook_command,
+            get_digest_settings_command,
+            save_digest_settings_command,
+            send_digest_command,
+            get_alert_rules_command,
+            save_alert_rule_command,
+            delete_alert_rule_command,
+            get_anomalies_command,
+            get_forecast_command,
+            export_forecast_command,
+            get_sales_targets_command,
+            save_sales_target_command,
+            delete_sales_target_command,
+            get_target_progress_command,
+            get_annotations_command,
+            save_annotation_command,
+            delete_annotation_command,
+            import_annotations_command,
+            get_summary_command,
+            get_steam_events_command,
+            import_steam_events_command,
+            get_event_impacts_command,
+            get_combined_discounts_command,
+            get_discount_runs_command,
+            get_price_history_command,
+            get_price_changes_command,
+            get_reference_prices_command,
+            import_reference_prices_command,
+            get_price_audit_command,
+            get_bundle_rules_command,
+            save_bundle_rule_command,
+            delete_bundle_rule_command,
+            get_parse_failures_command,
+            get_royalty_payees_command,
+            save_royalty_payee_command,
+            delete_royalty_payee_command,
+            get_royalty_rules_command,
+            save_royalty_rule_command,
+            delete_royalty_rule_command,
+            get_royalty_payouts_command,
+            save_royalty_payout_command,
+            delete_royalty_payout_command,
+            get_royalty_statements_command,
+            export_royalty_statements_command,
+            export_report_command,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -158,12 +216,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 fn on_setup(app_handle: AppHandle) {
     tokio::spawn(async move {
-        if let Err(e) = app::start(app_handle).await {
+        let version = app_handle.package_info().version.to_string();
+        if let Err(e) = app::start(Arc::new(TauriNotifier(app_handle)), version).await {
             log::error!("App start failed: {}", e);
         }
     });
 }
 
+
+// Forwards the core events to the webview and notifications to the system
+struct TauriNotifier(AppHandle);
+
+impl Notifier for TauriNotifier {
+    fn emit(&self, event: &str, payload: Value) {
+        let _ = self.0.emit(event, payload);
+    }
+
+    fn notify(&self, title: &str, body: &str) {
+        let _ = self.0.notification()
+            .builder()
+            .title(title)
+            .body(body)
+            .show();
+    }
+}
+
 // Helper wrapper for Tauri commands to handle errors and return JSON compatible errors
 type ErrorJSON = Value;
 async fn command_result<T, F>(fut: F) -> Result<T, ErrorJSON>
@@ -180,7 +257,7 @@ where
 async fn sync_command(app_handle: AppHandle) -> Result<String, ErrorJSON> {
     command_result(async {
         let connection = database::open().await?;
-        app::sync(&connection, &app_handle).await?;
+        app::sync(&connection, &TauriNotifier(app_handle)).await?;
         Ok(format!("Sync completed"))
     }).await
 }
@@ -228,7 +305,7 @@ async fn set_settings_command(settings: app::Settings, app_handle: AppHandle) ->
         let connection = database::open().await?;
         if let Err(e) = database::save_settings(&connection).await {
             if let ErrorType::Missing(_) = e {
-                app::create_password(&app_handle).await?;
+                app::create_password(&TauriNotifier(app_handle)).await?;
                 database::save_settings(&connection).await?;
             }
         }
@@ -258,26 +335,697 @@ async fn get_detailed_sales_command(from_date: Option<String>, to_date: Option<S
 
 
 #[tauri::command]
-async fn export_csv_command(path: String, from_date: Option<String>, to_date: Option<String>, delimiter: String) -> Result<String, ErrorJSON> {
+async fn get_parse_failures_command(from_date: Option<String>, to_date: Option<String>) -> Result<Vec<steam::ParseFailure>, ErrorJSON> {
     command_result(async {
         let connection = database::open().await?;
-        let rows = database::get_sale_details_by_date(&connection, from_date, to_date).await?;
-        let delimiter_byte = delimiter.bytes().next().unwrap_or(b',');
-        let result = app::export_to_csv(rows, path, delimiter_byte).await?;
+        let res = database::get_parse_failures(&connection, from_date, to_date).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn export_csv_command(path: String, from_date: Option<String>, to_date: Option<String>, delimiter: String, app_handle: AppHandle) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let options = export::ExportOptions {
+            format: export::ExportFormat::Csv,
+            delimiter: delimiter.bytes().next().unwrap_or(b','),
+            profile: None,
+            compression: export::Compression::None,
+        };
+        let result = export::export(&connection, from_date, to_date, path, options, move |progress| {
+            let _ = app_handle.emit("export-progress", progress);
+        }).await?;
+        Ok(result)
+    }).await
+}
+
+
+#[tauri::command]
+async fn export_command(path: String, format: export::ExportFormat, from_date: Option<String>, to_date: Option<String>, delimiter: Option<String>, profile_id: Option<i64>, compression: Option<export::Compression>, app_handle: AppHandle) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let profile = match profile_id {
+            Some(id) => Some(database::get_export_profile(&connection, id).await?),
+            None => None,
+        };
+        let options = export::ExportOptions {
+            format,
+            delimiter: delimiter.and_then(|d| d.bytes().next()).unwrap_or(b','),
+            profile,
+            compression: compression.unwrap_or_default(),
+        };
+        let result = export::export(&connection, from_date, to_date, path, options, move |progress| {
+            let _ = app_handle.emit("export-progress", progress);
+        }).await?;
         Ok(result)
     }).await
 }
 
 
 #[tauri::command]
-async fn copy_to_clipboard_command(from_date: Option<String>, to_date: Option<String>, delimiter: String) -> Result<String, ErrorJSON> {
+async fn get_export_profiles_command() -> Result<Vec<export::ExportProfile>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_export_profiles(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_export_profile_command(profile: export::ExportProfile) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        if let Some(date_format) = profile.date_format.as_deref() {
+            export::validate_date_format(date_format)?;
+        }
+        let connection = database::open().await?;
+        let id = database::save_export_profile(&connection, profile).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_export_profile_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_export_profile(&connection, id).await?;
+        Ok("Export profile deleted".into())
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_export_schedules_command() -> Result<Vec<schedule::ExportSchedule>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_export_schedules(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_export_schedule_command(schedule: schedule::ExportSchedule) -> Result<i64, ErrorJSON> {
     command_result(async {
         let connection = database::open().await?;
-        let rows = database::get_sale_details_by_date(&connection, from_date, to_date).await?;
+        let id = database::save_export_schedule(&connection, schedule::normalize(schedule)?).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_export_schedule_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_export_schedule(&connection, id).await?;
+        Ok("Export schedule deleted".into())
+    }).await
+}
+
+
+// Runs a schedule right away, regardless of when it last ran
+#[tauri::command]
+async fn run_export_schedule_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let export_schedule = database::get_export_schedule(&connection, id).await?;
+        let path = schedule::run_schedule(&connection, &export_schedule, chrono::Local::now().date_naive()).await?;
+        Ok(format!("{} exported to {}", export_schedule.name, path))
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_api_settings_command() -> Result<api::ApiSettings, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_api_settings(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+// Saving restarts the API server, an empty token is replaced by a new one
+#[tauri::command]
+async fn save_api_settings_command(settings: api::ApiSettings) -> Result<api::ApiSettings, ErrorJSON> {
+    command_result(async {
+        let mut settings = settings;
+        if settings.token.is_empty() {
+            settings.token = api::generate_token();
+        }
+        let connection = database::open().await?;
+        database::save_api_settings(&connection, settings.clone()).await?;
+        api::restart(connection, settings.clone()).await?;
+        Ok(settings)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_webhooks_command() -> Result<Vec<webhook::Webhook>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_webhooks(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_webhook_command(webhook: webhook::Webhook) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let id = database::save_webhook(&connection, webhook).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_webhook_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_webhook(&connection, id).await?;
+        Ok("Webhook deleted".into())
+    }).await
+}
+
+
+// Sends a test message right away, the webhook doesn't have to be saved
+#[tauri::command]
+async fn test_webhook_command(webhook: webhook::Webhook) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let message = webhook::WebhookMessage {
+            event: webhook::WebhookEvent::Test,
+            title: "Steamboard test".into(),
+            body: "This webhook is ready to receive your sales".into(),
+            data: serde_json::json!({}),
+        };
+        webhook::send(&webhook, &message).await?;
+        Ok(format!("Test message sent to {}", webhook.name))
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_digest_settings_command() -> Result<digest::DigestSettings, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_digest_settings(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_digest_settings_command(settings: digest::DigestSettings) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::save_digest_settings(&connection, settings).await?;
+        Ok("Digest settings saved".into())
+    }).await
+}
+
+
+// Sends the digest right away with the given settings, to check the SMTP setup
+#[tauri::command]
+async fn send_digest_command(settings: digest::DigestSettings) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let today = chrono::Local::now().date_naive();
+        let res = digest::send_digest(&connection, &settings, today).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_alert_rules_command() -> Result<Vec<alert::AlertRule>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_alert_rules(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_alert_rule_command(rule: alert::AlertRule) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let id = database::save_alert_rule(&connection, rule).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_alert_rule_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_alert_rule(&connection, id).await?;
+        Ok("Alert rule deleted".into())
+    }).await
+}
+
+
+// Abnormal days per app, flagged on the timeline
+#[tauri::command]
+async fn get_anomalies_command(from_date: String, to_date: String) -> Result<Vec<anomaly::Anomaly>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let from = app::parse_date(&from_date)?;
+        let to = app::parse_date(&to_date)?;
+        let res = anomaly::get_anomalies(&connection, from, to).await?;
+        Ok(res)
+    }).await
+}
+
+
+// 30 and 90 days projections per app and for the total, from the last complete day
+#[tauri::command]
+async fn get_forecast_command() -> Result<Vec<forecast::Forecast>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = forecast::forecast_all(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn export_forecast_command(path: String, delimiter: Option<String>) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let forecasts = forecast::forecast_all(&connection).await?;
+        let delimiter = delimiter.and_then(|d| d.bytes().next()).unwrap_or(b',');
+        let res = app::export_to_csv(forecast::to_rows(&forecasts), path, delimiter).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_sales_targets_command() -> Result<Vec<target::SalesTarget>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_sales_targets(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_sales_target_command(target: target::SalesTarget) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let id = database::save_sales_target(&connection, target::normalize(target)?).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_sales_target_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_sales_target(&connection, id).await?;
+        Ok("Sales target deleted".into())
+    }).await
+}
+
+
+// Recurring targets are measured on the period containing `date`, today by default
+#[tauri::command]
+async fn get_target_progress_command(date: Option<String>) -> Result<Vec<target::TargetProgress>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let date = match date {
+            Some(date) => app::parse_date(&date)?,
+            None => chrono::Local::now().date_naive(),
+        };
+        let res = target::get_progress(&connection, date).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_annotations_command(from_date: Option<String>, to_date: Option<String>) -> Result<Vec<annotation::Annotation>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_annotations(&connection, from_date, to_date).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_annotation_command(annotation: annotation::Annotation) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let id = database::save_annotation(&connection, annotation::normalize(annotation)?).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_annotation_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_annotation(&connection, id).await?;
+        Ok("Annotation deleted".into())
+    }).await
+}
+
+
+#[tauri::command]
+async fn import_annotations_command(path: String) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let annotations = annotation::read_csv(&path)?;
+        let count = database::save_annotations(&connection, annotations).await?;
+        Ok(format!("{} annotations imported", count))
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_summary_command(from_date: Option<String>, to_date: Option<String>, group_by: summary::GroupBy) -> Result<summary::SummaryResponse, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let rows = database::get_sale_details_by_date(&connection, from_date.clone(), to_date.clone()).await?;
+        let annotations = database::get_annotations(&connection, from_date, to_date).await?;
+        let bundles = bundle::load(&connection).await?;
+        Ok(summary::SummaryResponse {
+            rows: summary::summarize_with_bundles(&rows, group_by, &bundles),
+            total: summary::total(&rows),
+            annotations,
+        })
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_steam_events_command() -> Result<Vec<calendar::SteamEvent>, ErrorJSON> {
+    command_result(async {
+        let res = calendar::load_events()?;
+        Ok(res)
+    }).await
+}
+
+
+// Replaces the bundled sale calendar with a JSON file in the same format
+#[tauri::command]
+async fn import_steam_events_command(path: String) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let count = calendar::import_events(&path)?;
+        Ok(format!("{} Steam events imported", count))
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_event_impacts_command(from_date: Option<String>, to_date: Option<String>) -> Result<Vec<calendar::EventImpact>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let from = from_date.map(|d| app::parse_date(&d)).transpose()?;
+        let to = to_date.map(|d| app::parse_date(&d)).transpose()?;
+        let res = calendar::get_impacts(&connection, from, to).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_combined_discounts_command() -> Result<Vec<discount::CombinedDiscount>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_combined_discounts(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_discount_runs_command(from_date: Option<String>, to_date: Option<String>) -> Result<Vec<discount::DiscountRun>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let from = from_date.map(|d| app::parse_date(&d)).transpose()?;
+        let to = to_date.map(|d| app::parse_date(&d)).transpose()?;
+        let res = discount::get_runs(&connection, from, to).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_price_history_command(packageid: Option<i64>) -> Result<Vec<pricing::PricePeriod>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_price_history(&connection, packageid).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_price_changes_command(from_date: Option<String>, to_date: Option<String>, packageid: Option<i64>) -> Result<Vec<pricing::PriceChange>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let from = from_date.map(|d| app::parse_date(&d)).transpose()?;
+        let to = to_date.map(|d| app::parse_date(&d)).transpose()?;
+        let res = pricing::get_changes(&connection, from, to, packageid).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_reference_prices_command() -> Result<Vec<pricing::ReferencePrice>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_reference_prices(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn import_reference_prices_command(path: String) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let prices = pricing::read_reference_csv(&path)?;
+        let count = database::replace_reference_prices(&connection, prices).await?;
+        Ok(format!("{} reference prices imported", count))
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_price_audit_command() -> Result<Vec<pricing::PriceAudit>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = pricing::audit(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_bundle_rules_command() -> Result<Vec<bundle::BundleRule>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_bundle_rules(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_bundle_rule_command(rule: bundle::BundleRule) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        bundle::validate(&rule)?;
+        let res = database::save_bundle_rule(&connection, rule).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_bundle_rule_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_bundle_rule(&connection, id).await?;
+        Ok("Bundle rule deleted".into())
+    }).await
+}
+
+
+#[tauri::command]
+async fn copy_to_clipboard_command(from_date: Option<String>, to_date: Option<String>, delimiter: String, profile_id: Option<i64>, format: Option<clipboard::ClipboardFormat>, group_by: Option<summary::GroupBy>) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let rows = database::get_sale_details_by_date(&connection, from_date.clone(), to_date.clone()).await?;
         let delimiter_byte = delimiter.bytes().next().unwrap_or(b',');
-        let csv_string = app::write_csv(&rows, delimiter_byte)?;
+        let format = format.unwrap_or(clipboard::ClipboardFormat::Csv);
+        let content = match (group_by, profile_id) {
+            (Some(group_by), _) => {
+                let bundles = bundle::load(&connection).await?;
+                let annotations = database::get_annotations(&connection, from_date, to_date).await?;
+                clipboard::copy_summary(&rows, group_by, &bundles, &annotations, format, delimiter_byte)?
+            }
+            (None, Some(id)) => {
+                let profile = database::get_export_profile(&connection, id).await?;
+                clipboard::copy_rows(&rows, format, delimiter_byte, Some(&profile))?
+            }
+            (None, None) => clipboard::copy_rows(&rows, format, delimiter_byte, None)?,
+        };
         let mut clipboard = Clipboard::new().map_err(|e| app::ErrorType::BadFormatting(format!("Clipboard error: {}", e)))?;
-        clipboard.set_text(csv_string).map_err(|e| app::ErrorType::BadFormatting(format!("Clipboard error: {}", e)))?;
+        let copied = match content.html {
+            Some(html) => clipboard.set_html(html, Some(content.text)),
+            None => clipboard.set_text(content.text),
+        };
+        copied.map_err(|e| app::ErrorType::BadFormatting(format!("Clipboard error: {}", e)))?;
         Ok("Copied to system clipboard!".into())
     }).await
-}
\ No newline at end of file
+}
+
+
+#[tauri::command]
+async fn get_royalty_payees_command() -> Result<Vec<royalty::RoyaltyPayee>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_royalty_payees(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_royalty_payee_command(payee: royalty::RoyaltyPayee) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let id = database::save_royalty_payee(&connection, payee).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_royalty_payee_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_royalty_payee(&connection, id).await?;
+        Ok("Payee deleted".into())
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_royalty_rules_command() -> Result<Vec<royalty::RoyaltyRule>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_royalty_rules(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_royalty_rule_command(rule: royalty::RoyaltyRule) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        // Compared with the sales dates, they have to be formatted the same way
+        let start_date = rule.start_date.as_deref().filter(|date| !date.trim().is_empty()).map(|date| app::parse_date(date).map(app::format_date)).transpose()?;
+        let end_date = rule.end_date.as_deref().filter(|date| !date.trim().is_empty()).map(|date| app::parse_date(date).map(app::format_date)).transpose()?;
+        let id = database::save_royalty_rule(&connection, royalty::RoyaltyRule { start_date, end_date, ..rule }).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_royalty_rule_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_royalty_rule(&connection, id).await?;
+        Ok("Rule deleted".into())
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_royalty_payouts_command() -> Result<Vec<royalty::RoyaltyPayout>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = database::get_royalty_payouts(&connection).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn save_royalty_payout_command(payout: royalty::RoyaltyPayout) -> Result<i64, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        // Compared with the statement dates, they have to be formatted the same way
+        let to_date = app::format_date(app::parse_date(&payout.to_date)?);
+        let id = database::save_royalty_payout(&connection, royalty::RoyaltyPayout { to_date, ..payout }).await?;
+        Ok(id)
+    }).await
+}
+
+
+#[tauri::command]
+async fn delete_royalty_payout_command(id: i64) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        database::delete_royalty_payout(&connection, id).await?;
+        Ok("Payout deleted".into())
+    }).await
+}
+
+
+#[tauri::command]
+async fn get_royalty_statements_command(from_date: Option<String>, to_date: Option<String>) -> Result<Vec<royalty::RoyaltyStatement>, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let res = royalty::get_statements(&connection, from_date, to_date).await?;
+        Ok(res)
+    }).await
+}
+
+
+#[tauri::command]
+async fn export_royalty_statements_command(path: String, from_date: Option<String>, to_date: Option<String>, delimiter: String) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let connection = database::open().await?;
+        let statements = royalty::get_statements(&connection, from_date, to_date).await?;
+        let rows = royalty::export_rows(statements);
+        let delimiter_byte = delimiter.bytes().next().unwrap_or(b',');
+        let result = app::export_to_csv(rows, path, delimiter_byte).await?;
+        Ok(result)
+    }).await
+}
+
+
+#[tauri::command]
+async fn export_report_command(path: String, format: report::ReportFormat, period: report::ReportPeriod, date: Option<String>) -> Result<String, ErrorJSON> {
+    command_result(async {
+        let date = match date {
+            Some(date) => app::parse_date(&date)?,
+            None => chrono::Local::now().date_naive(),
+        };
+        let connection = database::open().await?;
+        let report = report::build_report(&connection, period, date).await?;
+        let result = report::export_report(report, path, format).await?;
+        Ok(result)
+    }).await
+}
@@ -0,0 +1,120 @@
+use crate::app::{self, ErrorType};
+use crate::bundle;
+use crate::database;
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Datelike, Local};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_rusqlite::Connection;
+
+
+// Sync health since the process started, exposed on `/metrics`
+#[derive(Default)]
+struct SyncMetrics {
+    last_success_timestamp: Option<f64>,
+    last_duration_seconds: f64,
+    last_rows_inserted: usize,
+    rows_inserted_total: usize,
+    syncs_total: u64,
+    errors_total: BTreeMap<&'static str, u64>,
+}
+
+static SYNC_METRICS: Lazy<Mutex<SyncMetrics>> = Lazy::new(|| Mutex::new(SyncMetrics::default()));
+
+
+pub fn record_sync(duration: Duration, rows_inserted: usize) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    if let Ok(mut metrics) = SYNC_METRICS.lock() {
+        metrics.last_success_timestamp = Some(timestamp);
+        metrics.last_duration_seconds = duration.as_secs_f64();
+        metrics.last_rows_inserted = rows_inserted;
+        metrics.rows_inserted_total += rows_inserted;
+        metrics.syncs_total += 1;
+    }
+}
+
+
+pub fn record_error(error: &ErrorType) {
+    if let Ok(mut metrics) = SYNC_METRICS.lock() {
+        *metrics.errors_total.entry(error.kind()).or_insert(0) += 1;
+    }
+}
+
+
+// Prometheus text format, business gauges are computed from the database on every scrape
+pub async fn render(connection: &Connection) -> Result<String, ErrorType> {
+    let today = Local::now().date_naive();
+    let month_start = today.with_day(1).unwrap_or(today);
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(month_start)), Some(app::format_date(today))).await?;
+    let today_rows: Vec<_> = rows.iter().filter(|r| r.date == app::format_date(today)).cloned().collect();
+
+    let mut text = String::new();
+    {
+        let metrics = SYNC_METRICS.lock().map_err(|_| ErrorType::Forbidden("Metrics are not available".into()))?;
+
+        header(&mut text, "steamboard_last_sync_success_timestamp_seconds", "gauge", "Unix time of the last successful sync");
+        if let Some(timestamp) = metrics.last_success_timestamp {
+            let _ = writeln!(text, "steamboard_last_sync_success_timestamp_seconds {}", timestamp);
+        }
+        header(&mut text, "steamboard_last_sync_duration_seconds", "gauge", "Duration of the last successful sync");
+        let _ = writeln!(text, "steamboard_last_sync_duration_seconds {}", metrics.last_duration_seconds);
+        header(&mut text, "steamboard_last_sync_rows_inserted", "gauge", "Sales rows added by the last successful sync");
+        let _ = writeln!(text, "steamboard_last_sync_rows_inserted {}", metrics.last_rows_inserted);
+        header(&mut text, "steamboard_sync_rows_inserted_total", "counter", "Sales rows added since start");
+        let _ = writeln!(text, "steamboard_sync_rows_inserted_total {}", metrics.rows_inserted_total);
+        header(&mut text, "steamboard_syncs_total", "counter", "Successful syncs since start");
+        let _ = writeln!(text, "steamboard_syncs_total {}", metrics.syncs_total);
+        header(&mut text, "steamboard_sync_errors_total", "counter", "Sync errors since start, by error type");
+        for (kind, count) in metrics.errors_total.iter() {
+            let _ = writeln!(text, "steamboard_sync_errors_total{{type=\"{}\"}} {}", kind, count);
+        }
+    }
+
+    let bundles = bundle::load(connection).await?;
+    let periods = [
+        ("today", summary::summarize_with_bundles(&today_rows, GroupBy::App, &bundles)),
+        ("month", summary::summarize_with_bundles(&rows, GroupBy::App, &bundles)),
+    ];
+
+    header(&mut text, "steamboard_net_units_sold", "gauge", "Net units sold per app");
+    for (period, apps) in periods.iter() {
+        for app in apps.iter() {
+            let _ = writeln!(text, "steamboard_net_units_sold{{{}}} {}", labels(app, period), app.net_units_sold);
+        }
+    }
+    header(&mut text, "steamboard_net_sales_usd", "gauge", "Net revenue per app in USD");
+    for (period, apps) in periods.iter() {
+        for app in apps.iter() {
+            // Amounts are stored as f32, rounding to cents hides the float noise
+            let _ = writeln!(text, "steamboard_net_sales_usd{{{}}} {:.2}", labels(app, period), app.net_sales_usd);
+        }
+    }
+
+    Ok(text)
+}
+
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+}
+
+
+fn labels(app: &SummaryRow, period: &str) -> String {
+    format!(
+        "appid=\"{}\",app=\"{}\",period=\"{}\"",
+        escape_label(&app.key),
+        escape_label(app.label.as_deref().unwrap_or("")),
+        period
+    )
+}
+
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,23 @@
+use serde_json::Value;
+
+
+// Where the core reports progress, events and notifications, so it doesn't depend on the UI it runs in.
+// The desktop app forwards them to the webview and the system notifications.
+pub trait Notifier: Send + Sync {
+    fn emit(&self, event: &str, payload: Value);
+    fn notify(&self, title: &str, body: &str);
+}
+
+
+// No UI at all, notifications end up in the logs and events are dropped
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn emit(&self, event: &str, payload: Value) {
+        log::debug!("{}: {}", event, payload);
+    }
+
+    fn notify(&self, title: &str, body: &str) {
+        log::info!("{} - {}", title, body);
+    }
+}
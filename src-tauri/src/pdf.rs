@@ -0,0 +1,115 @@
+// Minimal PDF writer: A4 pages, built-in Helvetica fonts, text and filled rectangles.
+// Enough for the generated reports without pulling a full PDF toolkit.
+use std::fmt::Write;
+
+pub const PAGE_WIDTH: f64 = 595.0;
+pub const PAGE_HEIGHT: f64 = 842.0;
+
+#[derive(Clone, Copy)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+#[derive(Default)]
+pub struct PdfDocument {
+    pages: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct PdfPage {
+    content: String,
+}
+
+impl PdfPage {
+    // Coordinates are in points, from the bottom left corner of the page
+    pub fn text(&mut self, x: f64, y: f64, size: f64, bold: bool, color: Color, text: &str) {
+        let font = if bold { "F2" } else { "F1" };
+        let _ = writeln!(
+            self.content,
+            "BT {} rg /{} {:.1} Tf {:.2} {:.2} Td ({}) Tj ET",
+            rgb(color), font, size, x, y, escape_text(text)
+        );
+    }
+
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Color) {
+        let _ = writeln!(self.content, "{} rg {:.2} {:.2} {:.2} {:.2} re f", rgb(color), x, y, width, height);
+    }
+
+    pub fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, width: f64, color: Color) {
+        let _ = writeln!(self.content, "{} RG {:.2} w {:.2} {:.2} m {:.2} {:.2} l S", rgb(color), width, x1, y1, x2, y2);
+    }
+}
+
+impl PdfDocument {
+    pub fn add_page(&mut self, page: PdfPage) {
+        self.pages.push(page.content);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Objects 1 to 4 are the catalog, the page tree and both fonts, then a page and its content per page
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            String::new(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string(),
+        ];
+
+        let mut kids = Vec::new();
+        for content in self.pages.iter() {
+            let page_id = objects.len() + 1;
+            kids.push(format!("{} 0 R", page_id));
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH, PAGE_HEIGHT, page_id + 1
+            ));
+            objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", content.len() + 1, content));
+        }
+        objects[1] = format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), kids.len());
+
+        let mut buffer = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(buffer.len());
+            let _ = write!(buffer, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+        }
+
+        let xref_offset = buffer.len();
+        let _ = write!(buffer, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(buffer, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(buffer, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref_offset);
+
+        buffer.into_bytes()
+    }
+}
+
+
+// Rough width of a string in Helvetica, used to right align numbers
+pub fn text_width(text: &str, size: f64) -> f64 {
+    text.chars().count() as f64 * size * 0.52
+}
+
+
+fn rgb(color: Color) -> String {
+    format!("{:.3} {:.3} {:.3}", color.0 as f64 / 255.0, color.1 as f64 / 255.0, color.2 as f64 / 255.0)
+}
+
+
+// Latin-1 characters are written as octal escapes so the content stream stays ASCII,
+// anything outside of WinAnsiEncoding is replaced
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(escaped, "\\{:03o}", c as u32);
+            }
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
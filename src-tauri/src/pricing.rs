@@ -0,0 +1,437 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use chrono::{Duration, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use tokio_rusqlite::Connection;
+
+// Days before and after a price change used to measure how fast it sold
+const VELOCITY_DAYS: i64 = 28;
+// Rounding to the currency's price points stays below that
+const DRIFT_TOLERANCE: f64 = 0.01;
+
+// Package, currency and country
+type PriceKey = (i64, String, String);
+
+
+// Most sold base price of a package in a currency and country on a day
+#[derive(Clone, Debug)]
+pub struct DailyPrice {
+    pub packageid: i64,
+    pub currency: String,
+    pub country_code: String,
+    pub date: String,
+    pub base_price: i64,
+}
+
+// Days a package kept the same base price in a currency and country, prices are in the smallest unit of
+// the currency. Regional pricing gives countries sharing a currency, like USD, their own prices
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PricePeriod {
+    pub packageid: i64,
+    pub package_name: Option<String>,
+    pub currency: String,
+    pub country_code: String,
+    pub from_date: String,
+    // Last day sold at that price
+    pub to_date: String,
+    pub base_price: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Velocity {
+    pub from_date: String,
+    pub to_date: String,
+    pub days: i64,
+    pub units_per_day: f64,
+    pub sales_usd_per_day: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PriceChange {
+    pub packageid: i64,
+    pub package_name: Option<String>,
+    pub currency: String,
+    pub country_code: String,
+    // First day sold at the new price
+    pub date: String,
+    pub old_price: i64,
+    pub new_price: i64,
+    // New price vs old price, -0.2 is 20% cheaper
+    pub price_change: f64,
+    pub before: Velocity,
+    pub after: Velocity,
+    // After per day vs before per day. None without sales before the change
+    pub units_change: Option<f64>,
+    pub sales_change: Option<f64>,
+}
+
+// Price we want a package to have in a currency, in hundredths like Steam reports `base_price`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReferencePrice {
+    pub id: Option<i64>,
+    // Default for every package without its own price when empty
+    pub packageid: Option<i64>,
+    pub currency: String,
+    pub price: i64,
+}
+
+// CSV line, prices are written like they are displayed, 14.99 and not 1499
+#[derive(Deserialize)]
+struct ReferencePriceRecord {
+    packageid: Option<i64>,
+    currency: String,
+    price: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    Matches,
+    Drifted,
+    // Drifted and still at the price of the first sale
+    NeverUpdated,
+    // In the reference matrix but never sold in that currency
+    NotObserved,
+    // Sold in a currency missing from the reference matrix
+    NoReference,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PriceAudit {
+    pub packageid: i64,
+    pub package_name: Option<String>,
+    pub currency: String,
+    // Every country sold in the currency is audited, None when it was never sold in it
+    pub country_code: Option<String>,
+    pub reference_price: Option<i64>,
+    pub observed_price: Option<i64>,
+    // Observed vs reference, 0.1 is 10% too expensive
+    pub difference: Option<f64>,
+    // First day of the observed price and last day it was seen
+    pub since: Option<String>,
+    pub last_seen: Option<String>,
+    pub status: AuditStatus,
+}
+
+
+// Periods ending before `from` are kept, the others are rebuilt from the daily prices so a sync only
+// reads the days it touched. Without `from`, or an empty history, everything is rebuilt
+pub async fn update_history(connection: &Connection, from: Option<String>) -> Result<usize, ErrorType> {
+    let history = database::get_price_history(connection, None).await?;
+    let from = match from {
+        Some(from) if !history.is_empty() => from,
+        _ => "1970-01-01".to_string(),
+    };
+
+    // Last period kept of every key, and where the rebuilt periods of the key start
+    let mut kept: BTreeMap<PriceKey, PricePeriod> = BTreeMap::new();
+    let mut starts: BTreeMap<PriceKey, String> = BTreeMap::new();
+    for period in history.into_iter() {
+        let key = (period.packageid, period.currency.clone(), period.country_code.clone());
+        if period.to_date < from {
+            kept.insert(key, period);
+        } else {
+            starts.entry(key).or_insert(period.from_date);
+        }
+    }
+    let first = starts.values().min().filter(|start| **start < from).unwrap_or(&from).clone();
+
+    let prices: Vec<DailyPrice> = database::get_daily_base_prices(connection, first).await?
+        .into_iter()
+        .filter(|p| p.date >= *starts.get(&(p.packageid, p.currency.clone(), p.country_code.clone())).unwrap_or(&from))
+        .collect();
+    let mut periods = build_history(&prices);
+    // The first rebuilt period continues the last one kept when the price didn't change
+    for period in periods.iter_mut() {
+        let key = (period.packageid, period.currency.clone(), period.country_code.clone());
+        if let Some(last) = kept.remove(&key).filter(|last| last.base_price == period.base_price) {
+            period.from_date = last.from_date;
+        }
+    }
+    database::replace_price_history(connection, from, periods).await
+}
+
+
+// `prices` are sorted by package, currency, country and date
+pub fn build_history(prices: &[DailyPrice]) -> Vec<PricePeriod> {
+    let mut history: Vec<PricePeriod> = Vec::new();
+    for price in prices.iter() {
+        match history.last_mut() {
+            Some(period) if same_key(period, price) && period.base_price == price.base_price => {
+                period.to_date = price.date.clone();
+            }
+            _ => history.push(PricePeriod {
+                packageid: price.packageid,
+                package_name: None,
+                currency: price.currency.clone(),
+                country_code: price.country_code.clone(),
+                from_date: price.date.clone(),
+                to_date: price.date.clone(),
+                base_price: price.base_price,
+            }),
+        }
+    }
+    history
+}
+
+
+fn same_key(period: &PricePeriod, price: &DailyPrice) -> bool {
+    period.packageid == price.packageid && period.currency == price.currency && period.country_code == price.country_code
+}
+
+
+// Base price changes made in the range, newest first
+pub async fn get_changes(connection: &Connection, from: Option<NaiveDate>, to: Option<NaiveDate>, packageid: Option<i64>) -> Result<Vec<PriceChange>, ErrorType> {
+    let history = load_history(connection, packageid).await?;
+
+    let mut changes: Vec<(&PricePeriod, &PricePeriod, Option<&PricePeriod>)> = Vec::new();
+    for (i, pair) in history.windows(2).enumerate() {
+        let (old, new) = (&pair[0], &pair[1]);
+        if old.packageid != new.packageid || old.currency != new.currency || old.country_code != new.country_code {
+            continue;
+        }
+        let Ok(date) = app::parse_date(&new.from_date) else {
+            continue;
+        };
+        if from.is_some_and(|from| date < from) || to.is_some_and(|to| date > to) {
+            continue;
+        }
+        let next = history.get(i + 2).filter(|next| next.packageid == new.packageid && next.currency == new.currency && next.country_code == new.country_code);
+        changes.push((old, new, next));
+    }
+    let (Some(first), Some(last)) = (changes.iter().map(|c| c.1.from_date.clone()).min(), changes.iter().map(|c| c.1.from_date.clone()).max()) else {
+        return Ok(Vec::new());
+    };
+
+    let first = app::parse_date(&first)? - Duration::days(VELOCITY_DAYS);
+    let last = app::parse_date(&last)? + Duration::days(VELOCITY_DAYS);
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(first)), Some(app::format_date(last))).await?;
+    let Some(last_sale) = rows.iter().map(|r| r.date.as_str()).max().and_then(|d| app::parse_date(d).ok()) else {
+        return Ok(Vec::new());
+    };
+
+    let mut price_changes = Vec::new();
+    for (old, new, next) in changes.into_iter() {
+        let rows: Vec<&CPartnerFinancialsDetailedSalesResult> = rows.iter()
+            .filter(|r| r.packageid.map(i64::from) == Some(new.packageid) && r.currency.as_deref() == Some(new.currency.as_str()))
+            .filter(|r| r.country_code.as_deref().unwrap_or_default() == new.country_code)
+            .collect();
+        let date = app::parse_date(&new.from_date)?;
+        // Windows stop at the previous and next changes so they only see one price
+        let before_from = (date - Duration::days(VELOCITY_DAYS)).max(app::parse_date(&old.from_date)?);
+        let after_to = match next {
+            Some(next) => app::parse_date(&next.from_date)? - Duration::days(1),
+            None => last_sale,
+        }.min(date + Duration::days(VELOCITY_DAYS - 1));
+
+        let before = velocity(&rows, before_from, date - Duration::days(1));
+        let after = velocity(&rows, date, after_to);
+        let change = |after: f64, before: f64| if before > 0.0 { Some(after / before - 1.0) } else { None };
+
+        price_changes.push(PriceChange {
+            packageid: new.packageid,
+            package_name: new.package_name.clone(),
+            currency: new.currency.clone(),
+            country_code: new.country_code.clone(),
+            date: new.from_date.clone(),
+            old_price: old.base_price,
+            new_price: new.base_price,
+            price_change: if old.base_price > 0 { new.base_price as f64 / old.base_price as f64 - 1.0 } else { 0.0 },
+            units_change: change(after.units_per_day, before.units_per_day),
+            sales_change: change(after.sales_usd_per_day, before.sales_usd_per_day),
+            before,
+            after,
+        });
+    }
+
+    price_changes.sort_by(|a, b| {
+        b.date.cmp(&a.date)
+            .then_with(|| a.packageid.cmp(&b.packageid))
+            .then_with(|| a.currency.cmp(&b.currency))
+            .then_with(|| a.country_code.cmp(&b.country_code))
+    });
+    Ok(price_changes)
+}
+
+
+// Reads `packageid,currency,price` lines, the whole file is rejected on the first bad line
+pub fn read_reference_csv(path: &str) -> Result<Vec<ReferencePrice>, ErrorType> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(Path::new(path))
+        .map_err(|e| ErrorType::BadFormatting(format!("Could not read {}: {}", path, e)))?;
+
+    let mut prices: Vec<ReferencePrice> = Vec::new();
+    for (i, record) in reader.deserialize::<ReferencePriceRecord>().enumerate() {
+        // The header is line 1
+        let line = i + 2;
+        let record = record.map_err(|e| ErrorType::BadFormatting(format!("Line {}: {}", line, e)))?;
+        let currency = record.currency.to_uppercase();
+        if currency.is_empty() {
+            return Err(ErrorType::BadFormatting(format!("Line {}: missing currency", line)));
+        }
+        let price = match parse_price(&record.price) {
+            Some(price) if price > 0.0 => (price * 100.0).round() as i64,
+            _ => return Err(ErrorType::BadFormatting(format!("Line {}: invalid price {}, expected a price like 1234.56 or 1.234,56", line, record.price))),
+        };
+        if prices.iter().any(|p| p.packageid == record.packageid && p.currency == currency) {
+            return Err(ErrorType::BadFormatting(format!("Line {}: {} is already priced", line, currency)));
+        }
+        prices.push(ReferencePrice { id: None, packageid: record.packageid, currency, price });
+    }
+
+    Ok(prices)
+}
+
+
+// The last separator is the decimal one, a comma only when followed by exactly two digits since
+// 22,000 KRW could be either. The other separator can group the thousands
+fn parse_price(text: &str) -> Option<f64> {
+    let (integer, decimals, grouping) = match text.rfind(['.', ',']) {
+        Some(i) if text[i..].starts_with(',') => {
+            if text.len() - i - 1 != 2 {
+                return None;
+            }
+            (&text[..i], &text[i + 1..], '.')
+        }
+        Some(i) => (&text[..i], &text[i + 1..], ','),
+        None => (text, "", ','),
+    };
+    if !(0..=2).contains(&decimals.len()) || !decimals.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let groups: Vec<&str> = integer.split(grouping).collect();
+    let grouped = groups.len() > 1;
+    for (i, group) in groups.iter().enumerate() {
+        let valid_len = match (grouped, i) {
+            (false, _) => !group.is_empty(),
+            (true, 0) => (1..=3).contains(&group.len()),
+            (true, _) => group.len() == 3,
+        };
+        if !valid_len || !group.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+    }
+
+    format!("{}.{}", groups.concat(), decimals).parse::<f64>().ok()
+}
+
+
+// Current price of every package in every country against the reference price of its currency
+pub async fn audit(connection: &Connection) -> Result<Vec<PriceAudit>, ErrorType> {
+    let references = database::get_reference_prices(connection).await?;
+    let history = load_history(connection, None).await?;
+
+    // Periods of each package and currency by country, in order
+    let mut observed: BTreeMap<(i64, String), BTreeMap<String, Vec<&PricePeriod>>> = BTreeMap::new();
+    let mut packages: BTreeMap<i64, Option<String>> = BTreeMap::new();
+    for period in history.iter() {
+        observed.entry((period.packageid, period.currency.clone()))
+            .or_default()
+            .entry(period.country_code.clone())
+            .or_default()
+            .push(period);
+        packages.entry(period.packageid).or_insert_with(|| period.package_name.clone());
+    }
+    for packageid in references.iter().filter_map(|r| r.packageid) {
+        packages.entry(packageid).or_default();
+    }
+
+    let mut audits = Vec::new();
+    for (packageid, package_name) in packages.iter() {
+        let own: Vec<&ReferencePrice> = references.iter().filter(|r| r.packageid == Some(*packageid)).collect();
+        let defaults = references.iter().filter(|r| r.packageid.is_none() && !own.iter().any(|o| o.currency == r.currency));
+        let mut prices: BTreeMap<String, i64> = own.iter().copied().chain(defaults).map(|r| (r.currency.clone(), r.price)).collect();
+
+        let currencies: BTreeSet<String> = observed.range((*packageid, String::new())..)
+            .take_while(|((id, _), _)| id == packageid)
+            .map(|((_, currency), _)| currency.clone())
+            .chain(prices.keys().cloned())
+            .collect();
+
+        for currency in currencies.into_iter() {
+            let reference_price = prices.remove(&currency);
+            let Some(countries) = observed.get(&(*packageid, currency.clone())) else {
+                audits.push(PriceAudit {
+                    packageid: *packageid,
+                    package_name: package_name.clone(),
+                    currency,
+                    country_code: None,
+                    reference_price,
+                    observed_price: None,
+                    difference: None,
+                    since: None,
+                    last_seen: None,
+                    status: AuditStatus::NotObserved,
+                });
+                continue;
+            };
+
+            for (country_code, periods) in countries.iter() {
+                let current = periods.last();
+                let observed_price = current.map(|p| p.base_price);
+                let difference = match (observed_price, reference_price) {
+                    (Some(observed), Some(reference)) if reference > 0 => Some(observed as f64 / reference as f64 - 1.0),
+                    _ => None,
+                };
+                let status = match difference {
+                    None => AuditStatus::NoReference,
+                    Some(difference) if difference.abs() <= DRIFT_TOLERANCE => AuditStatus::Matches,
+                    _ if periods.len() == 1 => AuditStatus::NeverUpdated,
+                    _ => AuditStatus::Drifted,
+                };
+
+                audits.push(PriceAudit {
+                    packageid: *packageid,
+                    package_name: package_name.clone(),
+                    currency: currency.clone(),
+                    country_code: Some(country_code.clone()),
+                    reference_price,
+                    observed_price,
+                    difference,
+                    since: current.map(|p| p.from_date.clone()),
+                    last_seen: current.map(|p| p.to_date.clone()),
+                    status,
+                });
+            }
+        }
+    }
+
+    Ok(audits)
+}
+
+
+async fn load_history(connection: &Connection, packageid: Option<i64>) -> Result<Vec<PricePeriod>, ErrorType> {
+    let mut history = database::get_price_history(connection, None).await?;
+    // First use after an upgrade, the next syncs keep it current
+    if history.is_empty() {
+        update_history(connection, None).await?;
+        history = database::get_price_history(connection, None).await?;
+    }
+    history.retain(|p| packageid.is_none_or(|id| p.packageid == id));
+    Ok(history)
+}
+
+
+// Days without sales count as zero
+fn velocity(rows: &[&CPartnerFinancialsDetailedSalesResult], from: NaiveDate, to: NaiveDate) -> Velocity {
+    let days = ((to - from).num_days() + 1).max(0);
+    let (from_date, to_date) = (app::format_date(from), app::format_date(to));
+    let (mut units, mut sales_usd) = (0, 0.0);
+    for row in rows.iter().filter(|r| from_date <= r.date && r.date <= to_date) {
+        units += row.net_units_sold.unwrap_or(0) as i64;
+        sales_usd += row.net_sales_usd.unwrap_or(0.0) as f64;
+    }
+
+    Velocity {
+        from_date,
+        to_date,
+        days,
+        units_per_day: if days > 0 { units as f64 / days as f64 } else { 0.0 },
+        sales_usd_per_day: if days > 0 { sales_usd / days as f64 } else { 0.0 },
+    }
+}
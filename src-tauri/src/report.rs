@@ -0,0 +1,400 @@
+use crate::app::{self, ErrorType};
+use crate::bundle;
+use crate::database;
+use crate::pdf::{self, Color, PdfDocument, PdfPage};
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::fmt::Write;
+use std::path::Path;
+use tokio::task;
+use tokio_rusqlite::Connection;
+
+const TOP_ROWS: usize = 10;
+
+const ACCENT: Color = Color(102, 192, 244);
+const TEXT: Color = Color(33, 37, 41);
+const MUTED: Color = Color(120, 128, 136);
+const PANEL: Color = Color(240, 243, 246);
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    Month,
+    Quarter,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Html,
+    Pdf,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Report {
+    pub title: String,
+    pub from_date: String,
+    pub to_date: String,
+    pub totals: SummaryRow,
+    pub previous_totals: SummaryRow,
+    pub daily: Vec<SummaryRow>,
+    pub top_packages: Vec<SummaryRow>,
+    pub countries: Vec<SummaryRow>,
+}
+
+
+// First and last day of the month or quarter containing `date`
+pub fn period_range(period: ReportPeriod, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let first_month = match period {
+        ReportPeriod::Month => date.month(),
+        ReportPeriod::Quarter => (date.month0() / 3) * 3 + 1,
+    };
+    let months = match period {
+        ReportPeriod::Month => 1,
+        ReportPeriod::Quarter => 3,
+    };
+    let from = NaiveDate::from_ymd_opt(date.year(), first_month, 1).unwrap_or(date);
+    let to = from.checked_add_months(chrono::Months::new(months)).unwrap_or(from) - Duration::days(1);
+    (from, to)
+}
+
+
+fn period_title(period: ReportPeriod, from: NaiveDate) -> String {
+    match period {
+        ReportPeriod::Month => from.format("%B %Y").to_string(),
+        ReportPeriod::Quarter => format!("Q{} {}", from.month0() / 3 + 1, from.year()),
+    }
+}
+
+
+pub async fn build_report(connection: &Connection, period: ReportPeriod, date: NaiveDate) -> Result<Report, ErrorType> {
+    let (from, to) = period_range(period, date);
+    let (previous_from, previous_to) = period_range(period, from - Duration::days(1));
+
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(from)), Some(app::format_date(to))).await?;
+    let previous_rows = database::get_sale_details_by_date(connection, Some(app::format_date(previous_from)), Some(app::format_date(previous_to))).await?;
+
+    let bundles = bundle::load(connection).await?;
+    let mut top_packages = summary::summarize_with_bundles(&rows, GroupBy::Package, &bundles);
+    top_packages.truncate(TOP_ROWS);
+    let mut countries = summary::summarize(&rows, GroupBy::Country);
+    countries.truncate(TOP_ROWS);
+
+    Ok(Report {
+        title: format!("Performance report - {}", period_title(period, from)),
+        from_date: app::format_date(from),
+        to_date: app::format_date(to),
+        totals: summary::total(&rows),
+        previous_totals: summary::total(&previous_rows),
+        daily: summary::summarize(&rows, GroupBy::Date),
+        top_packages,
+        countries,
+    })
+}
+
+
+pub async fn export_report(report: Report, path: String, format: ReportFormat) -> Result<String, ErrorType> {
+    let path_clone = path.clone();
+    task::spawn_blocking(move || -> Result<(), ErrorType> {
+        let bytes = match format {
+            ReportFormat::Html => render_html(&report).into_bytes(),
+            ReportFormat::Pdf => render_pdf(&report),
+        };
+        std::fs::write(Path::new(&path_clone), bytes).map_err(|e| ErrorType::BadFormatting(format!("Error while writing to file: {}", e)))?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| ErrorType::BadFormatting(format!("Error while creating the report: {}", e)))??;
+
+    Ok(format!("Report exported to {}", &path))
+}
+
+
+struct Kpi {
+    label: &'static str,
+    value: String,
+    change: String,
+}
+
+fn kpis(report: &Report) -> Vec<Kpi> {
+    let totals = &report.totals;
+    let previous = &report.previous_totals;
+    vec![
+        Kpi { label: "Net revenue", value: format_usd(totals.net_sales_usd), change: format_change(totals.net_sales_usd, previous.net_sales_usd) },
+        Kpi { label: "Gross revenue", value: format_usd(totals.gross_sales_usd), change: format_change(totals.gross_sales_usd, previous.gross_sales_usd) },
+        Kpi { label: "Net units", value: format_count(totals.net_units_sold), change: format_change(totals.net_units_sold as f64, previous.net_units_sold as f64) },
+        Kpi { label: "Units refunded", value: format_count(totals.gross_units_returned), change: format_change(totals.gross_units_returned as f64, previous.gross_units_returned as f64) },
+        Kpi { label: "Refund rate", value: format!("{:.1}%", totals.refund_rate() * 100.0), change: format_change(totals.refund_rate(), previous.refund_rate()) },
+        Kpi { label: "Activations", value: format_count(totals.gross_units_activated), change: format_change(totals.gross_units_activated as f64, previous.gross_units_activated as f64) },
+    ]
+}
+
+
+fn row_label(row: &SummaryRow) -> String {
+    row.label.clone().unwrap_or(row.key.clone())
+}
+
+
+pub fn render_html(report: &Report) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape_html(&report.title), HTML_STYLE
+    );
+    let _ = write!(html, "<h1>{}</h1>\n<p class=\"muted\">{} to {}</p>\n", escape_html(&report.title), report.from_date, report.to_date);
+
+    html.push_str("<div class=\"kpis\">\n");
+    for kpi in kpis(report) {
+        let _ = writeln!(
+            html,
+            "<div class=\"kpi\"><span class=\"muted\">{}</span><strong>{}</strong><span class=\"muted\">{} vs previous period</span></div>",
+            kpi.label, kpi.value, kpi.change
+        );
+    }
+    html.push_str("</div>\n");
+
+    html.push_str("<h2>Daily net revenue</h2>\n");
+    let daily: Vec<(String, f64)> = report.daily.iter().map(|d| (d.key.clone(), d.net_sales_usd)).collect();
+    html.push_str(&svg_column_chart(&daily, 760.0, 200.0));
+
+    for (title, rows) in [("Top packages", &report.top_packages), ("Countries", &report.countries)] {
+        let _ = writeln!(html, "<h2>{}</h2>", title);
+        let bars: Vec<(String, f64)> = rows.iter().map(|r| (row_label(r), r.net_sales_usd)).collect();
+        html.push_str(&svg_bar_chart(&bars, 760.0));
+        html.push_str("<table>\n<tr><th>Name</th><th>Net units</th><th>Refunds</th><th>Gross revenue</th><th>Net revenue</th></tr>\n");
+        for row in rows.iter() {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&row_label(row)),
+                format_count(row.net_units_sold),
+                format_count(row.gross_units_returned),
+                format_usd(row.gross_sales_usd),
+                format_usd(row.net_sales_usd)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<p class=\"muted\">Generated by Steamboard</p>\n</body>\n</html>\n");
+    html
+}
+
+
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; color: #212529; max-width: 800px; margin: 40px auto; }
+h1 { margin-bottom: 0; }
+h2 { margin-top: 40px; }
+.muted { color: #788088; font-size: 0.85em; }
+.kpis { display: grid; grid-template-columns: repeat(3, 1fr); gap: 12px; }
+.kpi { background: #f0f3f6; border-radius: 8px; padding: 12px; display: flex; flex-direction: column; gap: 4px; }
+.kpi strong { font-size: 1.4em; }
+table { width: 100%; border-collapse: collapse; margin-top: 12px; }
+th, td { text-align: right; padding: 6px 8px; border-bottom: 1px solid #f0f3f6; }
+th:first-child, td:first-child { text-align: left; }
+svg text { font-size: 10px; fill: #788088; }
+";
+
+
+fn svg_column_chart(values: &[(String, f64)], width: f64, height: f64) -> String {
+    let max = max_value(values);
+    let chart_height = height - 20.0;
+    let step = width / values.len().max(1) as f64;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n", w = width, h = height);
+    for (i, (label, value)) in values.iter().enumerate() {
+        let bar_height = (value.max(0.0) / max) * chart_height;
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#66c0f4\"><title>{}: {}</title></rect>",
+            i as f64 * step + 1.0, chart_height - bar_height, (step - 2.0).max(1.0), bar_height, escape_html(label), format_usd(*value)
+        );
+    }
+    if let (Some(first), Some(last)) = (values.first(), values.last()) {
+        let _ = writeln!(svg, "<text x=\"0\" y=\"{:.1}\">{}</text>", height - 4.0, escape_html(&first.0));
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>", width, height - 4.0, escape_html(&last.0));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+
+fn svg_bar_chart(values: &[(String, f64)], width: f64) -> String {
+    let max = max_value(values);
+    let row_height = 22.0;
+    let label_width = 200.0;
+    let height = row_height * values.len() as f64;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n", w = width, h = height);
+    for (i, (label, value)) in values.iter().enumerate() {
+        let y = i as f64 * row_height;
+        let bar_width = (value.max(0.0) / max) * (width - label_width - 90.0);
+        let _ = writeln!(svg, "<text x=\"0\" y=\"{:.1}\">{}</text>", y + 15.0, escape_html(&truncate(label, 32)));
+        let _ = writeln!(svg, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"3\" fill=\"#66c0f4\"/>", label_width, y + 4.0, bar_width, row_height - 8.0);
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>", label_width + bar_width + 6.0, y + 15.0, format_usd(*value));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+
+// Keeps track of the current page and vertical position while laying out the PDF
+struct PdfLayout {
+    document: PdfDocument,
+    page: PdfPage,
+    y: f64,
+}
+
+const MARGIN: f64 = 40.0;
+
+impl PdfLayout {
+    fn new() -> Self {
+        PdfLayout { document: PdfDocument::default(), page: PdfPage::default(), y: pdf::PAGE_HEIGHT - MARGIN }
+    }
+
+    fn reserve(&mut self, height: f64) {
+        if self.y - height < MARGIN {
+            let page = std::mem::take(&mut self.page);
+            self.document.add_page(page);
+            self.y = pdf::PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let page = std::mem::take(&mut self.page);
+        self.document.add_page(page);
+        self.document.to_bytes()
+    }
+}
+
+
+pub fn render_pdf(report: &Report) -> Vec<u8> {
+    let content_width = pdf::PAGE_WIDTH - 2.0 * MARGIN;
+    let mut layout = PdfLayout::new();
+
+    layout.y -= 20.0;
+    layout.page.text(MARGIN, layout.y, 20.0, true, TEXT, &report.title);
+    layout.y -= 16.0;
+    layout.page.text(MARGIN, layout.y, 10.0, false, MUTED, &format!("{} to {}", report.from_date, report.to_date));
+    layout.y -= 20.0;
+
+    // KPIs, three per row
+    let kpi_width = (content_width - 20.0) / 3.0;
+    let kpi_height = 56.0;
+    for (i, kpi) in kpis(report).iter().enumerate() {
+        if i % 3 == 0 {
+            layout.y -= kpi_height + 10.0;
+        }
+        let x = MARGIN + (i % 3) as f64 * (kpi_width + 10.0);
+        layout.page.rect(x, layout.y, kpi_width, kpi_height, PANEL);
+        layout.page.text(x + 10.0, layout.y + kpi_height - 16.0, 8.0, false, MUTED, kpi.label);
+        layout.page.text(x + 10.0, layout.y + 20.0, 14.0, true, TEXT, &kpi.value);
+        layout.page.text(x + 10.0, layout.y + 8.0, 7.0, false, MUTED, &format!("{} vs previous period", kpi.change));
+    }
+
+    // Daily net revenue
+    let chart_height = 140.0;
+    layout.y -= 30.0;
+    layout.page.text(MARGIN, layout.y, 13.0, true, TEXT, "Daily net revenue");
+    layout.y -= 10.0 + chart_height;
+    let daily: Vec<(String, f64)> = report.daily.iter().map(|d| (d.key.clone(), d.net_sales_usd)).collect();
+    let max = max_value(&daily);
+    let step = content_width / daily.len().max(1) as f64;
+    for (i, (_, value)) in daily.iter().enumerate() {
+        let bar_height = (value.max(0.0) / max) * chart_height;
+        layout.page.rect(MARGIN + i as f64 * step + 0.5, layout.y, (step - 1.0).max(0.5), bar_height, ACCENT);
+    }
+    layout.page.line(MARGIN, layout.y, MARGIN + content_width, layout.y, 0.5, MUTED);
+    if let (Some(first), Some(last)) = (daily.first(), daily.last()) {
+        layout.page.text(MARGIN, layout.y - 10.0, 7.0, false, MUTED, &first.0);
+        layout.page.text(MARGIN + content_width - pdf::text_width(&last.0, 7.0), layout.y - 10.0, 7.0, false, MUTED, &last.0);
+    }
+    layout.y -= 14.0;
+
+    // Breakdown tables with a bar behind each row
+    let row_height = 16.0;
+    let columns = [("Net units", 330.0), ("Net revenue", 420.0), ("Gross revenue", 515.0)];
+    for (title, rows) in [("Top packages", &report.top_packages), ("Countries", &report.countries)] {
+        layout.reserve(40.0 + row_height * rows.len().min(3) as f64);
+        layout.y -= 30.0;
+        layout.page.text(MARGIN, layout.y, 13.0, true, TEXT, title);
+        layout.y -= 16.0;
+        layout.page.text(MARGIN, layout.y, 8.0, true, MUTED, "Name");
+        for (label, right) in columns.iter() {
+            layout.page.text(MARGIN + right - pdf::text_width(label, 8.0), layout.y, 8.0, true, MUTED, label);
+        }
+
+        let max = rows.iter().map(|r| r.net_sales_usd).fold(f64::EPSILON, f64::max);
+        for row in rows.iter() {
+            layout.reserve(row_height);
+            layout.y -= row_height;
+            let bar_width = (row.net_sales_usd.max(0.0) / max) * 270.0;
+            layout.page.rect(MARGIN, layout.y - 4.0, bar_width, row_height - 2.0, PANEL);
+            layout.page.text(MARGIN + 4.0, layout.y, 9.0, false, TEXT, &truncate(&row_label(row), 45));
+            let values = [format_count(row.net_units_sold), format_usd(row.net_sales_usd), format_usd(row.gross_sales_usd)];
+            for (value, (_, right)) in values.iter().zip(columns.iter()) {
+                layout.page.text(MARGIN + right - pdf::text_width(value, 9.0), layout.y, 9.0, false, TEXT, value);
+            }
+        }
+    }
+
+    layout.reserve(30.0);
+    layout.y -= 30.0;
+    layout.page.text(MARGIN, layout.y, 8.0, false, MUTED, "Generated by Steamboard");
+
+    layout.finish()
+}
+
+
+fn max_value(values: &[(String, f64)]) -> f64 {
+    values.iter().map(|(_, v)| *v).fold(f64::EPSILON, f64::max)
+}
+
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let truncated: String = text.chars().take(max_chars - 3).collect();
+    format!("{}...", truncated)
+}
+
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+pub fn format_usd(value: f64) -> String {
+    let sign = if value < 0.0 { "-" } else { "" };
+    let cents = (value.abs() * 100.0).round() as i64;
+    format!("{}${}.{:02}", sign, format_count(cents / 100), cents % 100)
+}
+
+
+pub fn format_count(value: i64) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let mut formatted = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(c);
+    }
+    if value < 0 {
+        formatted.insert(0, '-');
+    }
+    formatted
+}
+
+
+fn format_change(current: f64, previous: f64) -> String {
+    if previous == 0.0 {
+        return "n/a".into();
+    }
+    format!("{:+.1}%", (current - previous) / previous.abs() * 100.0)
+}
@@ -0,0 +1,298 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use chrono::Duration;
+use tokio_rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+
+
+// Someone we pay royalties to, a co-developer or an external studio
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoyaltyPayee {
+    pub id: Option<i64>,
+    pub name: String,
+    // Statements below this amount are not paid out and carried forward
+    pub threshold_usd: f64,
+}
+
+// Share of an app or package revenue owed to a payee
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoyaltyRule {
+    pub id: Option<i64>,
+    pub payee_id: i64,
+    pub appid: Option<i32>,
+    pub packageid: Option<i32>,
+    // Percentage of the revenue left once the Valve fee is deducted
+    pub percentage: f64,
+    pub valve_fee_percentage: f64,
+    // Advance paid upfront, recouped from royalties before anything is payable
+    pub advance_usd: f64,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+}
+
+// Money actually sent to a payee, covering the statements up to `to_date`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoyaltyPayout {
+    pub id: Option<i64>,
+    pub payee_id: i64,
+    pub to_date: String,
+    pub amount_usd: f64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SalesTotals {
+    pub net_units_sold: i64,
+    pub gross_sales_usd: f64,
+    pub net_sales_usd: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RoyaltyStatementLine {
+    pub payee: String,
+    pub rule_id: Option<i64>,
+    pub appid: Option<i32>,
+    pub packageid: Option<i32>,
+    pub from_date: Option<String>,
+    pub to_date: Option<String>,
+    pub net_units_sold: i64,
+    pub gross_sales_usd: f64,
+    pub net_sales_usd: f64,
+    pub valve_fee_usd: f64,
+    pub royalty_base_usd: f64,
+    pub percentage: f64,
+    pub earned_usd: f64,
+    pub earned_to_date_usd: f64,
+    pub advance_usd: f64,
+    pub recouped_usd: f64,
+    pub advance_remaining_usd: f64,
+    pub payable_usd: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RoyaltyStatement {
+    pub payee_id: i64,
+    pub payee: String,
+    pub from_date: Option<String>,
+    pub to_date: Option<String>,
+    pub earned_usd: f64,
+    pub recouped_usd: f64,
+    pub payable_usd: f64,
+    // Payable before the period and not paid out yet
+    pub brought_forward_usd: f64,
+    pub threshold_usd: f64,
+    // Paid when the period and the balance brought forward reach the threshold, carried forward otherwise
+    pub paid_usd: f64,
+    pub carried_forward_usd: f64,
+    pub lines: Vec<RoyaltyStatementLine>,
+}
+
+// CSV line, every statement line followed by the payee's total with the threshold applied
+#[derive(Serialize, Clone, Debug)]
+pub struct RoyaltyExportRow {
+    pub payee: String,
+    pub rule_id: Option<i64>,
+    pub appid: Option<i32>,
+    pub packageid: Option<i32>,
+    pub from_date: Option<String>,
+    pub to_date: Option<String>,
+    pub net_units_sold: Option<i64>,
+    pub gross_sales_usd: Option<f64>,
+    pub net_sales_usd: Option<f64>,
+    pub valve_fee_usd: Option<f64>,
+    pub royalty_base_usd: Option<f64>,
+    pub percentage: Option<f64>,
+    pub earned_usd: f64,
+    pub earned_to_date_usd: Option<f64>,
+    pub advance_usd: Option<f64>,
+    pub recouped_usd: f64,
+    pub advance_remaining_usd: Option<f64>,
+    pub payable_usd: f64,
+    pub brought_forward_usd: Option<f64>,
+    pub threshold_usd: Option<f64>,
+    pub paid_usd: Option<f64>,
+    pub carried_forward_usd: Option<f64>,
+}
+
+
+pub async fn get_statements(connection: &Connection, from_date: Option<String>, to_date: Option<String>) -> Result<Vec<RoyaltyStatement>, ErrorType> {
+    let payees = database::get_royalty_payees(connection).await?;
+    let rules = database::get_royalty_rules(connection).await?;
+    let payouts = database::get_royalty_payouts(connection).await?;
+
+    let mut statements = Vec::new();
+    for payee in payees.iter() {
+        let Some(payee_id) = payee.id else { continue };
+        let mut lines = Vec::new();
+
+        for rule in rules.iter().filter(|r| r.payee_id == payee_id) {
+            if let Some(line) = get_statement_line(connection, payee, rule, from_date.clone(), to_date.clone()).await? {
+                lines.push(line);
+            }
+        }
+
+        let earned_usd = lines.iter().map(|l| l.earned_usd).sum();
+        let recouped_usd = lines.iter().map(|l| l.recouped_usd).sum();
+        let payable_usd: f64 = lines.iter().map(|l| l.payable_usd).sum();
+
+        let brought_forward_usd = match &from_date {
+            Some(from_date) => {
+                let mut payable_before_usd = 0.0;
+                for rule in rules.iter().filter(|r| r.payee_id == payee_id) {
+                    payable_before_usd += get_payable_before(connection, rule, from_date).await?;
+                }
+                let paid_before_usd: f64 = payouts.iter()
+                    .filter(|p| p.payee_id == payee_id && p.to_date < *from_date)
+                    .map(|p| p.amount_usd)
+                    .sum();
+                (payable_before_usd - paid_before_usd).max(0.0)
+            }
+            None => 0.0,
+        };
+        let due_usd = brought_forward_usd + payable_usd;
+        let paid_usd = if due_usd >= payee.threshold_usd { due_usd } else { 0.0 };
+
+        statements.push(RoyaltyStatement {
+            payee_id,
+            payee: payee.name.clone(),
+            from_date: from_date.clone(),
+            to_date: to_date.clone(),
+            earned_usd,
+            recouped_usd,
+            payable_usd,
+            brought_forward_usd,
+            threshold_usd: payee.threshold_usd,
+            paid_usd,
+            carried_forward_usd: due_usd - paid_usd,
+            lines,
+        });
+    }
+
+    Ok(statements)
+}
+
+
+async fn get_statement_line(connection: &Connection, payee: &RoyaltyPayee, rule: &RoyaltyRule, from_date: Option<String>, to_date: Option<String>) -> Result<Option<RoyaltyStatementLine>, ErrorType> {
+    // Restrict the period to the dates the rule is active
+    let period_from = std::cmp::max(from_date, rule.start_date.clone());
+    let period_to = match (to_date, rule.end_date.clone()) {
+        (Some(to), Some(end)) => Some(std::cmp::min(to, end)),
+        (to, end) => to.or(end),
+    };
+
+    if let (Some(from), Some(to)) = (&period_from, &period_to) {
+        if from > to {
+            return Ok(None);
+        }
+    }
+
+    let period = database::get_sales_totals(connection, rule.appid, rule.packageid, period_from.clone(), period_to.clone()).await?;
+    let cumulative = database::get_sales_totals(connection, rule.appid, rule.packageid, rule.start_date.clone(), period_to.clone()).await?;
+
+    let earned_usd = royalty_for(rule, period.net_sales_usd);
+    let earned_to_date_usd = royalty_for(rule, cumulative.net_sales_usd);
+    let earned_before_usd = earned_to_date_usd - earned_usd;
+
+    let recouped_usd = earned_to_date_usd.min(rule.advance_usd) - earned_before_usd.min(rule.advance_usd);
+    let payable_usd = (earned_to_date_usd - rule.advance_usd).max(0.0) - (earned_before_usd - rule.advance_usd).max(0.0);
+    let valve_fee_usd = period.net_sales_usd * rule.valve_fee_percentage / 100.0;
+
+    Ok(Some(RoyaltyStatementLine {
+        payee: payee.name.clone(),
+        rule_id: rule.id,
+        appid: rule.appid,
+        packageid: rule.packageid,
+        from_date: period_from,
+        to_date: period_to,
+        net_units_sold: period.net_units_sold,
+        gross_sales_usd: period.gross_sales_usd,
+        net_sales_usd: period.net_sales_usd,
+        valve_fee_usd,
+        royalty_base_usd: period.net_sales_usd - valve_fee_usd,
+        percentage: rule.percentage,
+        earned_usd,
+        earned_to_date_usd,
+        advance_usd: rule.advance_usd,
+        recouped_usd,
+        advance_remaining_usd: (rule.advance_usd - earned_to_date_usd).max(0.0),
+        payable_usd,
+    }))
+}
+
+
+// Payable by the rule before `from_date`, advances recouped
+async fn get_payable_before(connection: &Connection, rule: &RoyaltyRule, from_date: &str) -> Result<f64, ErrorType> {
+    let day_before = app::format_date(app::parse_date(from_date)? - Duration::days(1));
+    let to_date = match &rule.end_date {
+        Some(end_date) => std::cmp::min(day_before, end_date.clone()),
+        None => day_before,
+    };
+    if rule.start_date.as_ref().is_some_and(|start_date| *start_date > to_date) {
+        return Ok(0.0);
+    }
+
+    let totals = database::get_sales_totals(connection, rule.appid, rule.packageid, rule.start_date.clone(), Some(to_date)).await?;
+    Ok((royalty_for(rule, totals.net_sales_usd) - rule.advance_usd).max(0.0))
+}
+
+
+pub fn export_rows(statements: Vec<RoyaltyStatement>) -> Vec<RoyaltyExportRow> {
+    let mut rows = Vec::new();
+    for statement in statements.into_iter() {
+        for line in statement.lines.into_iter() {
+            rows.push(RoyaltyExportRow {
+                payee: line.payee,
+                rule_id: line.rule_id,
+                appid: line.appid,
+                packageid: line.packageid,
+                from_date: line.from_date,
+                to_date: line.to_date,
+                net_units_sold: Some(line.net_units_sold),
+                gross_sales_usd: Some(line.gross_sales_usd),
+                net_sales_usd: Some(line.net_sales_usd),
+                valve_fee_usd: Some(line.valve_fee_usd),
+                royalty_base_usd: Some(line.royalty_base_usd),
+                percentage: Some(line.percentage),
+                earned_usd: line.earned_usd,
+                earned_to_date_usd: Some(line.earned_to_date_usd),
+                advance_usd: Some(line.advance_usd),
+                recouped_usd: line.recouped_usd,
+                advance_remaining_usd: Some(line.advance_remaining_usd),
+                payable_usd: line.payable_usd,
+                brought_forward_usd: None,
+                threshold_usd: None,
+                paid_usd: None,
+                carried_forward_usd: None,
+            });
+        }
+        rows.push(RoyaltyExportRow {
+            payee: statement.payee,
+            rule_id: None,
+            appid: None,
+            packageid: None,
+            from_date: statement.from_date,
+            to_date: statement.to_date,
+            net_units_sold: None,
+            gross_sales_usd: None,
+            net_sales_usd: None,
+            valve_fee_usd: None,
+            royalty_base_usd: None,
+            percentage: None,
+            earned_usd: statement.earned_usd,
+            earned_to_date_usd: None,
+            advance_usd: None,
+            recouped_usd: statement.recouped_usd,
+            advance_remaining_usd: None,
+            payable_usd: statement.payable_usd,
+            brought_forward_usd: Some(statement.brought_forward_usd),
+            threshold_usd: Some(statement.threshold_usd),
+            paid_usd: Some(statement.paid_usd),
+            carried_forward_usd: Some(statement.carried_forward_usd),
+        });
+    }
+    rows
+}
+
+
+fn royalty_for(rule: &RoyaltyRule, net_sales_usd: f64) -> f64 {
+    net_sales_usd * (1.0 - rule.valve_fee_percentage / 100.0) * rule.percentage / 100.0
+}
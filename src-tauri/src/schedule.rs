@@ -0,0 +1,224 @@
+use crate::app::{self, ErrorType};
+use crate::database;
+use crate::export::{self, Compression, ExportFormat, ExportOptions};
+use crate::notifier::Notifier;
+use crate::report::{self, ReportPeriod};
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+use tokio_rusqlite::Connection;
+
+const FILENAME_TOKENS: [&str; 9] = ["today", "from", "to", "year", "month", "day", "week", "quarter", "ext"];
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleFrequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+// Date range of the export, relative to the day the schedule runs
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelativeRange {
+    PreviousDay,
+    PreviousWeek,
+    PreviousMonth,
+    PreviousQuarter,
+    PreviousYear,
+    Last7Days,
+    Last30Days,
+    MonthToDate,
+    YearToDate,
+    All,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportSchedule {
+    pub id: Option<i64>,
+    pub name: String,
+    pub enabled: bool,
+    pub frequency: ScheduleFrequency,
+    pub range: RelativeRange,
+    pub format: ExportFormat,
+    pub profile_id: Option<i64>,
+    pub delimiter: String,
+    pub compression: Compression,
+    pub directory: String,
+    // Supports {today}, {from}, {to}, {year}, {month}, {day}, {week}, {quarter} and {ext}
+    pub filename_template: String,
+    // `yyyy/MM/dd` date of the last run, a failed run also waits for the next period
+    pub last_run: Option<String>,
+    // Why the last run failed, None when it succeeded
+    pub last_error: Option<String>,
+}
+
+
+// Catches what would make every run fail. A schedule whose last run failed runs again once saved
+pub fn normalize(schedule: ExportSchedule) -> Result<ExportSchedule, ErrorType> {
+    if schedule.profile_id.is_some() && !matches!(schedule.format, ExportFormat::Csv) {
+        return Err(ErrorType::BadFormatting("Export profiles can only be used with CSV exports".into()));
+    }
+    let directory = schedule.directory.trim().to_string();
+    if !Path::new(&directory).is_dir() {
+        return Err(ErrorType::BadFormatting(format!("The export directory \"{}\" doesn't exist", directory)));
+    }
+    validate_template(&schedule.filename_template)?;
+
+    let (last_run, last_error) = match schedule.last_error {
+        Some(_) => (None, None),
+        None => (schedule.last_run, None),
+    };
+    Ok(ExportSchedule { directory, last_run, last_error, ..schedule })
+}
+
+
+fn validate_template(template: &str) -> Result<(), ErrorType> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err(ErrorType::BadFormatting(format!("Unclosed {{ in the file name template {}", template)));
+        };
+        let token = &rest[start + 1..start + end];
+        if !FILENAME_TOKENS.contains(&token) {
+            return Err(ErrorType::BadFormatting(format!("Unknown token {{{}}} in the file name template", token)));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(ErrorType::BadFormatting(format!("Unopened }} in the file name template {}", template)));
+    }
+    Ok(())
+}
+
+
+// Runs every enabled schedule that didn't run yet in the current day, week or month
+pub async fn run_due_schedules(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
+    let today = Local::now().date_naive();
+    let schedules = database::get_export_schedules(connection).await?;
+
+    for schedule in schedules.into_iter().filter(|s| s.enabled && is_due(s.frequency, s.last_run.as_deref(), today)) {
+        match run_schedule(connection, &schedule, today).await {
+            Ok(path) => {
+                log::info!("Scheduled export \"{}\" written to {}", schedule.name, path);
+                notifier.notify("Export completed", &format!("{} exported to {}", schedule.name, path));
+            }
+            Err(e) => {
+                log::error!("Scheduled export \"{}\" failed: {}", schedule.name, e);
+                notifier.notify("Export failed", &format!("{}: {}", schedule.name, e));
+                // Not retried before the next period, a broken schedule would notify on every sync
+                if let Some(id) = schedule.id {
+                    database::set_export_schedule_last_run(connection, id, app::format_date(today), Some(e.to_string())).await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+// Exports the schedule range as of `today`, returns the path of the written file
+pub async fn run_schedule(connection: &Connection, schedule: &ExportSchedule, today: NaiveDate) -> Result<String, ErrorType> {
+    let (from, to) = resolve_range(schedule.range, today);
+    let profile = match schedule.profile_id {
+        Some(id) => Some(database::get_export_profile(connection, id).await?),
+        None => None,
+    };
+
+    std::fs::create_dir_all(&schedule.directory)
+        .map_err(|e| ErrorType::BadFormatting(format!("Could not create export directory: {}", e)))?;
+    let file_name = file_name(&schedule.filename_template, schedule.format, schedule.compression, today, from, to);
+    let path = Path::new(&schedule.directory).join(file_name).to_string_lossy().into_owned();
+
+    let options = ExportOptions {
+        format: schedule.format,
+        delimiter: schedule.delimiter.bytes().next().unwrap_or(b','),
+        profile,
+        compression: schedule.compression,
+    };
+    export::export(connection, from.map(app::format_date), to.map(app::format_date), path.clone(), options, |_| {}).await?;
+
+    if let Some(id) = schedule.id {
+        database::set_export_schedule_last_run(connection, id, app::format_date(today), None).await?;
+    }
+
+    Ok(path)
+}
+
+
+// True when `last_run` is not in the same day, ISO week or month as `today`
+pub fn is_due(frequency: ScheduleFrequency, last_run: Option<&str>, today: NaiveDate) -> bool {
+    let last_run = match last_run.map(app::parse_date) {
+        Some(Ok(last_run)) => last_run,
+        _ => return true,
+    };
+    match frequency {
+        ScheduleFrequency::Daily => last_run < today,
+        ScheduleFrequency::Weekly => last_run.iso_week() != today.iso_week(),
+        ScheduleFrequency::Monthly => (last_run.year(), last_run.month()) != (today.year(), today.month()),
+    }
+}
+
+
+pub fn resolve_range(range: RelativeRange, today: NaiveDate) -> (Option<NaiveDate>, Option<NaiveDate>) {
+    let yesterday = today - Duration::days(1);
+    let (from, to) = match range {
+        RelativeRange::PreviousDay => (yesterday, yesterday),
+        RelativeRange::PreviousWeek => {
+            let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+            (monday - Duration::days(7), monday - Duration::days(1))
+        }
+        RelativeRange::PreviousMonth => report::period_range(ReportPeriod::Month, today.with_day(1).unwrap_or(today) - Duration::days(1)),
+        RelativeRange::PreviousQuarter => {
+            let (quarter_start, _) = report::period_range(ReportPeriod::Quarter, today);
+            report::period_range(ReportPeriod::Quarter, quarter_start - Duration::days(1))
+        }
+        RelativeRange::PreviousYear => (
+            NaiveDate::from_ymd_opt(today.year() - 1, 1, 1).unwrap_or(today),
+            NaiveDate::from_ymd_opt(today.year() - 1, 12, 31).unwrap_or(today),
+        ),
+        RelativeRange::Last7Days => (today - Duration::days(7), yesterday),
+        RelativeRange::Last30Days => (today - Duration::days(30), yesterday),
+        RelativeRange::MonthToDate => (today.with_day(1).unwrap_or(today), today),
+        RelativeRange::YearToDate => (NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap_or(today), today),
+        RelativeRange::All => return (None, None),
+    };
+    (Some(from), Some(to))
+}
+
+
+fn file_name(template: &str, format: ExportFormat, compression: Compression, today: NaiveDate, from: Option<NaiveDate>, to: Option<NaiveDate>) -> String {
+    let template = if template.trim().is_empty() { "steamboard-{from}-{to}.{ext}" } else { template };
+    // Dates use dashes, slashes are not allowed in file names
+    let format_date = |date: Option<NaiveDate>| date.map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or("all".into());
+    let extension = match format {
+        ExportFormat::Csv => "csv",
+        ExportFormat::Json => "json",
+        ExportFormat::Ndjson => "ndjson",
+        ExportFormat::Parquet => "parquet",
+        ExportFormat::Arrow => "arrow",
+        ExportFormat::Xlsx => "xlsx",
+        ExportFormat::Ods => "ods",
+    };
+
+    let name = template
+        .replace("{today}", &format_date(Some(today)))
+        .replace("{from}", &format_date(from))
+        .replace("{to}", &format_date(to))
+        .replace("{year}", &today.format("%Y").to_string())
+        .replace("{month}", &today.format("%m").to_string())
+        .replace("{day}", &today.format("%d").to_string())
+        .replace("{week}", &format!("{:02}", today.iso_week().week()))
+        .replace("{quarter}", &(today.month0() / 3 + 1).to_string())
+        .replace("{ext}", extension)
+        .replace(['/', '\\'], "-");
+
+    match compression {
+        Compression::None => name,
+        Compression::Gzip => format!("{}.gz", name),
+        Compression::Zip => format!("{}.zip", name),
+    }
+}
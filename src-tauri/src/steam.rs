@@ -1,9 +1,12 @@
 use reqwest::{Client, StatusCode};
 use serde_json::Value;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use crate::app::ErrorType;
 use serde_with::serde_as;
 use serde_with::DisplayFromStr;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeSet, HashSet};
 
 #[derive(Debug, Deserialize)]
 pub struct ChangedDates {
@@ -11,7 +14,7 @@ pub struct ChangedDates {
     pub result_highwatermark: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug)]
 pub struct DetailedSales {
     pub results: Option<Vec<CPartnerFinancialsDetailedSalesResult>>,
     pub key_request_info: Option<Vec<CPartnerFinancialsKeyRequestInfo>>,
@@ -24,6 +27,44 @@ pub struct DetailedSales {
     pub country_info: Option<Vec<CPartnerFinancialsCountryInfo>>,
     pub partner_info: Option<Vec<CPartnerFinancialsPartnerInfo>>,
     pub max_id: String,
+    pub parse_failures: Vec<ParseFailure>,
+}
+
+// A row of the response that could not be deserialized, kept so the rest of the page can still be saved
+#[derive(Clone, Debug, Serialize)]
+pub struct ParseFailure {
+    pub date: String,
+    pub section: String,
+    pub error: String,
+    pub raw: String,
+}
+
+// Fields Valve sent that we don't know about, and rows we failed to parse during a sync
+#[derive(Clone, Debug, Serialize, Default)]
+pub struct SchemaDrift {
+    pub unknown_fields: BTreeSet<String>,
+    pub parse_failures: usize,
+}
+
+impl SchemaDrift {
+    pub fn record(&mut self, detailed_sales: &DetailedSales) {
+        for result in detailed_sales.results.iter().flatten() {
+            let Some(extra) = &result.extra else { continue };
+            if let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(extra) {
+                self.unknown_fields.extend(fields.keys().cloned());
+            }
+        }
+        self.parse_failures += detailed_sales.parse_failures.len();
+    }
+
+    pub fn merge(&mut self, other: SchemaDrift) {
+        self.unknown_fields.extend(other.unknown_fields);
+        self.parse_failures += other.parse_failures;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unknown_fields.is_empty() && self.parse_failures == 0
+    }
 }
 
 #[serde_as]
@@ -78,8 +119,18 @@ pub struct CPartnerFinancialsDetailedSalesResult {
     pub region: Option<String>,
     pub combined_discount_name: Option<String>,
     pub total_discount_percentage: Option<i32>,
+    // JSON object of the fields Valve sent that are not part of this struct
+    pub extra: Option<String>,
 }
 
+// Every field name of a sales result, used to spot the ones added by Valve
+static KNOWN_RESULT_FIELDS: Lazy<HashSet<String>> = Lazy::new(|| {
+    match serde_json::to_value(CPartnerFinancialsDetailedSalesResult::default()) {
+        Ok(Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => HashSet::new(),
+    }
+});
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct CPartnerFinancialsDiscountInfo {
     pub discountid: i32,
@@ -200,15 +251,104 @@ pub async fn get_detailed_sales(token: Option<String>, date: &str, highwatermark
         .await
         .map_err(|e| ErrorType::BadFormatting(format!("Failed to parse JSON response: {}", e)))?;
 
-    let json_results = json_body["response"].clone();
+    let mut json_results = json_body["response"].clone();
 
-    let response: DetailedSales = serde_json::from_value(json_results)
+    let max_id: String = serde_json::from_value(take_section(&mut json_results, "max_id"))
         .map_err(|e| ErrorType::BadFormatting(format!("Failed to deserialize response: {}", e)))?;
 
+    let mut parse_failures = Vec::new();
+    let response = DetailedSales {
+        results: parse_sales_results(&mut json_results, date, &mut parse_failures),
+        key_request_info: parse_rows(&mut json_results, date, "key_request_info", &mut parse_failures),
+        package_info: parse_rows(&mut json_results, date, "package_info", &mut parse_failures),
+        app_info: parse_rows(&mut json_results, date, "app_info", &mut parse_failures),
+        bundle_info: parse_rows(&mut json_results, date, "bundle_info", &mut parse_failures),
+        discount_info: parse_rows(&mut json_results, date, "discount_info", &mut parse_failures),
+        combined_discount_info: parse_rows(&mut json_results, date, "combined_discount_info", &mut parse_failures),
+        game_item_info: parse_rows(&mut json_results, date, "game_item_info", &mut parse_failures),
+        country_info: parse_rows(&mut json_results, date, "country_info", &mut parse_failures),
+        partner_info: parse_rows(&mut json_results, date, "partner_info", &mut parse_failures),
+        max_id,
+        parse_failures,
+    };
+
+    for failure in response.parse_failures.iter() {
+        log::warn!("Could not parse a {} row for {}: {}", failure.section, failure.date, failure.error);
+    }
+
     Ok(response)
 }
 
 
+fn take_section(response: &mut Value, section: &str) -> Value {
+    response.get_mut(section).map(Value::take).unwrap_or(Value::Null)
+}
+
+
+// Deserialize a section row by row, so a single malformed row doesn't fail the whole page
+fn parse_rows<T: DeserializeOwned>(response: &mut Value, date: &str, section: &str, parse_failures: &mut Vec<ParseFailure>) -> Option<Vec<T>> {
+    let rows = match take_section(response, section) {
+        Value::Null => return None,
+        Value::Array(rows) => rows,
+        other => {
+            parse_failures.push(ParseFailure {
+                date: date.to_string(),
+                section: section.to_string(),
+                error: "Expected an array".into(),
+                raw: other.to_string(),
+            });
+            return None;
+        }
+    };
+
+    let mut parsed = Vec::new();
+    for row in rows {
+        match T::deserialize(&row) {
+            Ok(r) => parsed.push(r),
+            Err(e) => parse_failures.push(ParseFailure {
+                date: date.to_string(),
+                section: section.to_string(),
+                error: e.to_string(),
+                raw: row.to_string(),
+            }),
+        }
+    }
+
+    Some(parsed)
+}
+
+
+// Same as `parse_rows`, but unknown fields are kept in `extra` instead of being dropped
+fn parse_sales_results(response: &mut Value, date: &str, parse_failures: &mut Vec<ParseFailure>) -> Option<Vec<CPartnerFinancialsDetailedSalesResult>> {
+    let rows: Vec<Value> = parse_rows(response, date, "results", parse_failures)?;
+
+    let mut results = Vec::new();
+    for row in rows {
+        match CPartnerFinancialsDetailedSalesResult::deserialize(&row) {
+            Ok(mut result) => {
+                if let Value::Object(fields) = row {
+                    let extra: serde_json::Map<String, Value> = fields.into_iter()
+                        .filter(|(key, _)| !KNOWN_RESULT_FIELDS.contains(key))
+                        .collect();
+                    if !extra.is_empty() {
+                        result.extra = Some(Value::Object(extra).to_string());
+                    }
+                }
+                results.push(result);
+            }
+            Err(e) => parse_failures.push(ParseFailure {
+                date: date.to_string(),
+                section: "results".into(),
+                error: e.to_string(),
+                raw: row.to_string(),
+            }),
+        }
+    }
+
+    Some(results)
+}
+
+
 pub async fn check_api_key(api_key: Option<String>) -> Result<String, ErrorType> {
     let api_key = api_key.ok_or(ErrorType::BadToken("API key not found".into()))?;
     let url = format!("https://partner.steam-api.com/IPartnerFinancialsService/GetDetailedSales/v001/?key={}", api_key);
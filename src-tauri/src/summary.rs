@@ -0,0 +1,165 @@
+use crate::annotation::Annotation;
+use crate::bundle::BundleAllocation;
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Date,
+    App,
+    Package,
+    Country,
+    Discount,
+}
+
+// Summary with the events of the period, so charts can show them next to the numbers
+#[derive(Serialize, Clone, Debug)]
+pub struct SummaryResponse {
+    pub rows: Vec<SummaryRow>,
+    pub total: SummaryRow,
+    pub annotations: Vec<Annotation>,
+}
+
+// Totals of the sales rows sharing the same key
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SummaryRow {
+    pub key: String,
+    pub label: Option<String>,
+    pub gross_units_sold: i64,
+    pub gross_units_returned: i64,
+    pub gross_units_activated: i64,
+    pub net_units_sold: i64,
+    pub gross_sales_usd: f64,
+    pub gross_returns_usd: f64,
+    pub net_tax_usd: f64,
+    pub net_sales_usd: f64,
+}
+
+impl SummaryRow {
+    pub fn add(&mut self, row: &CPartnerFinancialsDetailedSalesResult) {
+        self.gross_units_sold += row.gross_units_sold.unwrap_or(0) as i64;
+        self.gross_units_returned += row.gross_units_returned.unwrap_or(0) as i64;
+        self.gross_units_activated += row.gross_units_activated.unwrap_or(0) as i64;
+        self.net_units_sold += row.net_units_sold.unwrap_or(0) as i64;
+        self.gross_sales_usd += row.gross_sales_usd.unwrap_or(0.0) as f64;
+        self.gross_returns_usd += row.gross_returns_usd.unwrap_or(0.0) as f64;
+        self.net_tax_usd += row.net_tax_usd.unwrap_or(0.0) as f64;
+        self.net_sales_usd += row.net_sales_usd.unwrap_or(0.0) as f64;
+    }
+
+    // Adds the totals of another summary, the key and label stay
+    pub fn merge(&mut self, other: &SummaryRow) {
+        self.gross_units_sold += other.gross_units_sold;
+        self.gross_units_returned += other.gross_units_returned;
+        self.gross_units_activated += other.gross_units_activated;
+        self.net_units_sold += other.net_units_sold;
+        self.gross_sales_usd += other.gross_sales_usd;
+        self.gross_returns_usd += other.gross_returns_usd;
+        self.net_tax_usd += other.net_tax_usd;
+        self.net_sales_usd += other.net_sales_usd;
+    }
+
+    // Share of the units sold that were refunded, between 0 and 1
+    pub fn refund_rate(&self) -> f64 {
+        if self.gross_units_sold == 0 {
+            return 0.0;
+        }
+        self.gross_units_returned as f64 / self.gross_units_sold as f64
+    }
+}
+
+
+pub fn total(rows: &[CPartnerFinancialsDetailedSalesResult]) -> SummaryRow {
+    let mut total = SummaryRow {
+        key: "total".into(),
+        label: Some("Total".into()),
+        ..Default::default()
+    };
+    for row in rows.iter() {
+        total.add(row);
+    }
+    total
+}
+
+
+// Days are sorted chronologically, every other grouping by net revenue, highest first
+pub fn summarize(rows: &[CPartnerFinancialsDetailedSalesResult], group_by: GroupBy) -> Vec<SummaryRow> {
+    let mut groups: BTreeMap<String, SummaryRow> = BTreeMap::new();
+
+    for row in rows.iter() {
+        let (key, label) = group_key(row, group_by);
+        let summary = groups.entry(key.clone()).or_insert_with(|| SummaryRow {
+            key,
+            ..Default::default()
+        });
+        if summary.label.is_none() {
+            summary.label = label;
+        }
+        summary.add(row);
+    }
+
+    let mut summaries: Vec<SummaryRow> = groups.into_values().collect();
+    if group_by != GroupBy::Date {
+        summaries.sort_by(|a, b| b.net_sales_usd.total_cmp(&a.net_sales_usd));
+    }
+    summaries
+}
+
+
+// Totals per group and per day, days without sales are missing from the inner maps
+pub fn daily_by_group(rows: &[CPartnerFinancialsDetailedSalesResult], group_by: GroupBy) -> BTreeMap<String, BTreeMap<String, SummaryRow>> {
+    let mut groups: BTreeMap<String, BTreeMap<String, SummaryRow>> = BTreeMap::new();
+
+    for row in rows.iter() {
+        let (key, label) = group_key(row, group_by);
+        let summary = groups.entry(key.clone())
+            .or_default()
+            .entry(row.date.clone())
+            .or_insert_with(|| SummaryRow {
+                key,
+                ..Default::default()
+            });
+        if summary.label.is_none() {
+            summary.label = label;
+        }
+        summary.add(row);
+    }
+
+    groups
+}
+
+
+// App and package totals include their share of the bundles with an allocation rule, the total of
+// the raw rows stays the reference since bundle units are credited to every member
+pub fn summarize_with_bundles(rows: &[CPartnerFinancialsDetailedSalesResult], group_by: GroupBy, bundles: &BundleAllocation) -> Vec<SummaryRow> {
+    match group_by {
+        GroupBy::App | GroupBy::Package if !bundles.is_empty() => summarize(&bundles.apply(rows), group_by),
+        _ => summarize(rows, group_by),
+    }
+}
+
+
+fn group_key(row: &CPartnerFinancialsDetailedSalesResult, group_by: GroupBy) -> (String, Option<String>) {
+    match group_by {
+        GroupBy::Date => (row.date.clone(), None),
+        GroupBy::App => match row.appid.or(row.primary_appid) {
+            Some(appid) => (appid.to_string(), row.app_name.clone()),
+            None => ("none".into(), Some("Unknown app".into())),
+        },
+        GroupBy::Package => match row.packageid {
+            Some(packageid) => (packageid.to_string(), row.package_name.clone()),
+            None => ("none".into(), Some("No package".into())),
+        },
+        GroupBy::Country => match &row.country_code {
+            Some(country_code) => (country_code.clone(), row.country_name.clone()),
+            None => ("none".into(), Some("Unknown country".into())),
+        },
+        GroupBy::Discount => match row.combined_discount_id {
+            Some(discount_id) if discount_id != 0 => (discount_id.to_string(), row.combined_discount_name.clone()),
+            _ => ("none".into(), Some("Full price".into())),
+        },
+    }
+}
@@ -0,0 +1,180 @@
+use crate::app::{self, ErrorType};
+use crate::bundle::{self, BundleAllocation};
+use crate::database;
+use crate::notifier::Notifier;
+use crate::report::{self, format_count, format_usd, ReportPeriod};
+use crate::summary::{self, GroupBy, SummaryRow};
+use chrono::{Datelike, Local, NaiveDate};
+use serde::{Serialize, Deserialize};
+use tokio_rusqlite::Connection;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetMetric {
+    Units,
+    NetRevenue,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetPeriod {
+    Month,
+    Quarter,
+    Year,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SalesTarget {
+    pub id: Option<i64>,
+    pub name: String,
+    // All apps when empty
+    pub appid: Option<i64>,
+    pub metric: TargetMetric,
+    pub period: TargetPeriod,
+    // Any day of the targeted period, the target repeats every period when empty
+    pub period_date: Option<String>,
+    pub value: f64,
+    pub notify: bool,
+    // `yyyy/MM/dd` start of the last period the target was notified for
+    pub last_notified: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TargetProgress {
+    pub target: SalesTarget,
+    pub from_date: String,
+    pub to_date: String,
+    pub days_elapsed: i64,
+    pub days_total: i64,
+    pub actual: f64,
+    // Share of the target done, 1 when reached
+    pub progress: f64,
+    // What should be done by now if sales were spread evenly over the period
+    pub expected_to_date: f64,
+    // Current run rate extended to the end of the period
+    pub projected: f64,
+    pub achieved: bool,
+    pub on_track: bool,
+}
+
+
+// The period date is stored as `yyyy/MM/dd` like the sales, blank means a recurring target
+pub fn normalize(target: SalesTarget) -> Result<SalesTarget, ErrorType> {
+    if target.value.is_nan() || target.value <= 0.0 {
+        return Err(ErrorType::BadFormatting(format!("The target \"{}\" needs a value above 0", target.name)));
+    }
+    let period_date = match target.period_date.as_deref().map(str::trim) {
+        Some(date) if !date.is_empty() => Some(app::format_date(app::parse_date(date)?)),
+        _ => None,
+    };
+
+    Ok(SalesTarget { period_date, ..target })
+}
+
+
+// Progress of every target, recurring ones for the period containing `date`
+pub async fn get_progress(connection: &Connection, date: NaiveDate) -> Result<Vec<TargetProgress>, ErrorType> {
+    let targets = database::get_sales_targets(connection).await?;
+    let bundles = bundle::load(connection).await?;
+    let mut progress = Vec::new();
+    for target in targets.into_iter() {
+        progress.push(target_progress(connection, target, &bundles, date).await?);
+    }
+    Ok(progress)
+}
+
+
+// Notifies the targets reached in the current period, once per period
+pub async fn check_targets(connection: &Connection, notifier: &dyn Notifier) -> Result<(), ErrorType> {
+    let today = Local::now().date_naive();
+    let targets = database::get_sales_targets(connection).await?;
+    let bundles = bundle::load(connection).await?;
+
+    for target in targets.into_iter().filter(|t| t.notify) {
+        let (from, _) = target_range(&target, today);
+        if target.last_notified.as_deref() == Some(app::format_date(from).as_str()) {
+            continue;
+        }
+        let progress = target_progress(connection, target, &bundles, today).await?;
+        if !progress.achieved {
+            continue;
+        }
+
+        let body = format!("{} reached: {}", progress.target.name, format_value(progress.target.metric, progress.actual));
+        log::info!("Sending notification: Target reached - {}", body);
+        notifier.notify("Target reached", &body);
+        if let Some(id) = progress.target.id {
+            database::set_sales_target_last_notified(connection, id, progress.from_date.clone()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+
+async fn target_progress(connection: &Connection, target: SalesTarget, bundles: &BundleAllocation, date: NaiveDate) -> Result<TargetProgress, ErrorType> {
+    let (from, to) = target_range(&target, date);
+    let today = Local::now().date_naive();
+    let rows = database::get_sale_details_by_date(connection, Some(app::format_date(from)), Some(app::format_date(to.min(today)))).await?;
+
+    let totals = match target.appid {
+        Some(appid) => summary::summarize_with_bundles(&rows, GroupBy::App, bundles)
+            .into_iter()
+            .find(|s| s.key == appid.to_string())
+            .unwrap_or_default(),
+        None => summary::total(&rows),
+    };
+    let actual = metric_value(&totals, target.metric);
+
+    let days_total = (to - from).num_days() + 1;
+    let days_elapsed = ((today.min(to) - from).num_days() + 1).clamp(0, days_total);
+    let expected_to_date = target.value * days_elapsed as f64 / days_total as f64;
+    let projected = if days_elapsed > 0 { actual / days_elapsed as f64 * days_total as f64 } else { 0.0 };
+    let progress = if target.value > 0.0 { actual / target.value } else { 1.0 };
+
+    Ok(TargetProgress {
+        from_date: app::format_date(from),
+        to_date: app::format_date(to),
+        days_elapsed,
+        days_total,
+        actual,
+        progress,
+        expected_to_date,
+        projected,
+        achieved: actual >= target.value,
+        on_track: actual >= target.value || projected >= target.value,
+        target,
+    })
+}
+
+
+fn target_range(target: &SalesTarget, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let date = target.period_date.as_deref()
+        .and_then(|d| app::parse_date(d).ok())
+        .unwrap_or(date);
+    match target.period {
+        TargetPeriod::Month => report::period_range(ReportPeriod::Month, date),
+        TargetPeriod::Quarter => report::period_range(ReportPeriod::Quarter, date),
+        TargetPeriod::Year => (
+            NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap_or(date),
+            NaiveDate::from_ymd_opt(date.year(), 12, 31).unwrap_or(date),
+        ),
+    }
+}
+
+
+fn metric_value(summary: &SummaryRow, metric: TargetMetric) -> f64 {
+    match metric {
+        TargetMetric::Units => summary.net_units_sold as f64,
+        TargetMetric::NetRevenue => summary.net_sales_usd,
+    }
+}
+
+
+fn format_value(metric: TargetMetric, value: f64) -> String {
+    match metric {
+        TargetMetric::Units => format!("{} units", format_count(value as i64)),
+        TargetMetric::NetRevenue => format_usd(value),
+    }
+}
@@ -0,0 +1,179 @@
+use crate::app::ErrorType;
+use crate::database;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use reqwest::{Client, StatusCode};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_rusqlite::Connection;
+
+
+const MAX_ATTEMPTS: u32 = 4;
+
+// Deliveries still running, a one-shot CLI waits for them before its runtime goes away
+static PENDING: Lazy<Mutex<Vec<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    Json,
+    Discord,
+    Slack,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    SyncCompleted,
+    NewSales,
+    // The net revenue of a single sync reached the webhook threshold
+    Threshold,
+    SyncFailed,
+    Test,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+    pub id: Option<i64>,
+    pub name: String,
+    pub url: String,
+    pub format: WebhookFormat,
+    pub events: Vec<WebhookEvent>,
+    pub threshold_usd: Option<f64>,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct WebhookMessage {
+    pub event: WebhookEvent,
+    pub title: String,
+    pub body: String,
+    // Sent as is in the generic JSON format
+    pub data: Value,
+}
+
+
+// Sends the message to every enabled webhook listening to its event, in the background so a slow
+// endpoint never holds the sync, see `wait_for_deliveries`. New sales also fire the threshold event
+// of the webhooks whose threshold is reached by `data.net_sales_usd`.
+pub async fn dispatch(connection: &Connection, message: WebhookMessage) {
+    let webhooks = match database::get_webhooks(connection).await {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            log::error!("Could not load webhooks: {}", e);
+            return;
+        }
+    };
+    let net_sales_usd = message.data.get("net_sales_usd").and_then(Value::as_f64).unwrap_or(0.0);
+
+    for webhook in webhooks.into_iter().filter(|w| w.enabled) {
+        let mut messages = Vec::new();
+        if webhook.events.contains(&message.event) {
+            messages.push(message.clone());
+        }
+        if let (WebhookEvent::NewSales, Some(threshold)) = (message.event, webhook.threshold_usd) {
+            if webhook.events.contains(&WebhookEvent::Threshold) && net_sales_usd >= threshold {
+                messages.push(WebhookMessage {
+                    event: WebhookEvent::Threshold,
+                    title: format!("${:.2} of new sales", net_sales_usd),
+                    body: format!("The last sync brought more than ${:.2}", threshold),
+                    data: message.data.clone(),
+                });
+            }
+        }
+
+        for message in messages {
+            let webhook = webhook.clone();
+            let handle = tokio::spawn(async move {
+                if let Err(e) = send(&webhook, &message).await {
+                    log::error!("Webhook \"{}\" failed: {}", webhook.name, e);
+                }
+            });
+            let mut pending = PENDING.lock().await;
+            pending.retain(|h| !h.is_finished());
+            pending.push(handle);
+        }
+    }
+}
+
+
+// Waits for the deliveries in flight, retries give up after `MAX_ATTEMPTS` so it doesn't hang
+pub async fn wait_for_deliveries() {
+    let pending = std::mem::take(&mut *PENDING.lock().await);
+    for handle in pending {
+        let _ = handle.await;
+    }
+}
+
+
+// Retries network errors, rate limits and server errors with an exponential backoff
+pub async fn send(webhook: &Webhook, message: &WebhookMessage) -> Result<(), ErrorType> {
+    let client = Client::new();
+    let payload = payload(webhook.format, message);
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+
+    loop {
+        let error = match client.post(&webhook.url).json(&payload).timeout(Duration::from_secs(10)).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let error = ErrorType::BadHttpRequest(format!("Webhook answered {}", status));
+                if !(status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS) {
+                    return Err(error);
+                }
+                error
+            }
+            Err(e) => ErrorType::BadHttpRequest(format!("Webhook request failed: {}", e)),
+        };
+
+        if attempt == MAX_ATTEMPTS {
+            return Err(error);
+        }
+        log::warn!("Webhook \"{}\" attempt {} failed, retrying in {:?}: {}", webhook.name, attempt, delay, error);
+        sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+
+fn payload(format: WebhookFormat, message: &WebhookMessage) -> Value {
+    match format {
+        WebhookFormat::Json => json!({
+            "event": message.event,
+            "title": message.title,
+            "body": message.body,
+            "data": message.data,
+            "timestamp": Utc::now().to_rfc3339(),
+        }),
+        WebhookFormat::Discord => json!({
+            "username": "Steamboard",
+            "embeds": [{
+                "title": message.title,
+                "description": message.body,
+                "color": color(message.event),
+            }],
+        }),
+        WebhookFormat::Slack => json!({
+            "text": format!("{}: {}", message.title, message.body),
+            "blocks": [
+                { "type": "header", "text": { "type": "plain_text", "text": message.title } },
+                { "type": "section", "text": { "type": "mrkdwn", "text": message.body } },
+            ],
+        }),
+    }
+}
+
+
+fn color(event: WebhookEvent) -> u32 {
+    match event {
+        WebhookEvent::SyncFailed => 0xd9534f,
+        WebhookEvent::Threshold => 0xf0ad4e,
+        _ => 0x5cb85c,
+    }
+}
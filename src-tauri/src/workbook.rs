@@ -0,0 +1,316 @@
+use crate::annotation::{self, Annotation};
+use crate::app::ErrorType;
+use crate::bundle::BundleAllocation;
+use crate::export;
+use crate::forecast::{self, Forecast, ForecastMetric};
+use crate::steam::CPartnerFinancialsDetailedSalesResult;
+use crate::summary::{self, GroupBy, SummaryRow};
+use arrow::array::{Array, AsArray};
+use arrow::datatypes::{DataType, Date32Type, Float64Type, Int32Type, Int64Type};
+use chrono::{Datelike, Duration, NaiveDate};
+use rust_xlsxwriter::{ExcelDateTime, Format, Workbook};
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+
+// Spreadsheet independent cell, so both XLSX and ODS writers share the same sheets
+pub enum Cell {
+    Empty,
+    Text(String),
+    Number(f64),
+    Usd(f64),
+    Date(NaiveDate),
+}
+
+pub struct Sheet {
+    pub name: String,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+
+pub fn sheets(rows: &[CPartnerFinancialsDetailedSalesResult], bundles: &BundleAllocation, forecasts: &[Forecast], annotations: &[Annotation]) -> Result<Vec<Sheet>, ErrorType> {
+    let daily = summary::summarize(rows, GroupBy::Date);
+    let events: Vec<String> = daily.iter().map(|s| annotation::labels_on(annotations, &s.key).join(", ")).collect();
+    let mut daily_sheet = summary_sheet("Daily", "Date", daily, true);
+    // Events next to the numbers they may explain
+    if !annotations.is_empty() {
+        daily_sheet.headers.push("Events".into());
+        for (row, events) in daily_sheet.rows.iter_mut().zip(events) {
+            row.push(if events.is_empty() { Cell::Empty } else { Cell::Text(events) });
+        }
+    }
+
+    let mut sheets = vec![
+        sales_sheet(rows)?,
+        daily_sheet,
+        summary_sheet("Apps", "App", summary::summarize_with_bundles(rows, GroupBy::App, bundles), false),
+        summary_sheet("Countries", "Country", summary::summarize(rows, GroupBy::Country), false),
+        summary_sheet("Discounts", "Discount", summary::summarize(rows, GroupBy::Discount), false),
+    ];
+    if !forecasts.is_empty() {
+        sheets.push(forecast_sheet(forecasts));
+    }
+    if !annotations.is_empty() {
+        sheets.push(annotation_sheet(annotations));
+    }
+    Ok(sheets)
+}
+
+
+// Raw rows, typed from the same columns as the Parquet and Arrow exports
+fn sales_sheet(rows: &[CPartnerFinancialsDetailedSalesResult]) -> Result<Sheet, ErrorType> {
+    let batch = export::record_batch(rows)?;
+    let schema = batch.schema();
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+
+    let mut sheet_rows: Vec<Vec<Cell>> = (0..batch.num_rows()).map(|_| Vec::new()).collect();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let is_usd = field.name().ends_with("_usd");
+        for (i, row) in sheet_rows.iter_mut().enumerate() {
+            if column.is_null(i) {
+                row.push(Cell::Empty);
+                continue;
+            }
+            let cell = match column.data_type() {
+                DataType::Int32 => Cell::Number(column.as_primitive::<Int32Type>().value(i) as f64),
+                DataType::Int64 => Cell::Number(column.as_primitive::<Int64Type>().value(i) as f64),
+                DataType::Float64 if is_usd => Cell::Usd(column.as_primitive::<Float64Type>().value(i)),
+                DataType::Float64 => Cell::Number(column.as_primitive::<Float64Type>().value(i)),
+                DataType::Date32 => Cell::Date(epoch + Duration::days(column.as_primitive::<Date32Type>().value(i) as i64)),
+                DataType::Utf8 => Cell::Text(column.as_string::<i32>().value(i).to_string()),
+                _ => Cell::Empty,
+            };
+            row.push(cell);
+        }
+    }
+
+    Ok(Sheet {
+        name: "Sales".into(),
+        headers: schema.fields().iter().map(|f| f.name().clone()).collect(),
+        rows: sheet_rows,
+    })
+}
+
+
+fn summary_sheet(name: &str, key_header: &str, summaries: Vec<SummaryRow>, key_is_date: bool) -> Sheet {
+    let mut headers = vec![key_header.to_string()];
+    if !key_is_date {
+        headers.push("Name".into());
+    }
+    headers.extend([
+        "Gross units sold",
+        "Gross units returned",
+        "Gross units activated",
+        "Net units sold",
+        "Gross sales (USD)",
+        "Gross returns (USD)",
+        "Net tax (USD)",
+        "Net sales (USD)",
+    ].map(String::from));
+
+    let rows = summaries.into_iter().map(|s| {
+        let mut row = Vec::new();
+        if key_is_date {
+            row.push(crate::app::parse_date(&s.key).map(Cell::Date).unwrap_or(Cell::Text(s.key.clone())));
+        } else {
+            row.push(Cell::Text(s.key.clone()));
+            row.push(s.label.clone().map(Cell::Text).unwrap_or(Cell::Empty));
+        }
+        row.extend([
+            Cell::Number(s.gross_units_sold as f64),
+            Cell::Number(s.gross_units_returned as f64),
+            Cell::Number(s.gross_units_activated as f64),
+            Cell::Number(s.net_units_sold as f64),
+            Cell::Usd(s.gross_sales_usd),
+            Cell::Usd(s.gross_returns_usd),
+            Cell::Usd(s.net_tax_usd),
+            Cell::Usd(s.net_sales_usd),
+        ]);
+        row
+    }).collect();
+
+    Sheet { name: name.into(), headers, rows }
+}
+
+
+// Daily projections of every app, units and revenue side by side
+fn forecast_sheet(forecasts: &[Forecast]) -> Sheet {
+    let headers = ["App", "Name", "Metric", "Date", "Forecast", "Lower (95%)", "Upper (95%)"].map(String::from).to_vec();
+    let rows = forecast::to_rows(forecasts).into_iter().map(|f| {
+        let number = |value: f64| match f.metric {
+            ForecastMetric::Units => Cell::Number(value.round()),
+            ForecastMetric::Revenue => Cell::Usd(value),
+        };
+        vec![
+            Cell::Text(f.key.clone()),
+            f.label.clone().map(Cell::Text).unwrap_or(Cell::Empty),
+            Cell::Text(match f.metric {
+                ForecastMetric::Units => "Net units".into(),
+                ForecastMetric::Revenue => "Net sales (USD)".into(),
+            }),
+            crate::app::parse_date(&f.date).map(Cell::Date).unwrap_or(Cell::Text(f.date.clone())),
+            number(f.value),
+            number(f.lower),
+            number(f.upper),
+        ]
+    }).collect();
+
+    Sheet { name: "Forecast".into(), headers, rows }
+}
+
+
+fn annotation_sheet(annotations: &[Annotation]) -> Sheet {
+    let headers = ["From", "To", "App", "Label", "Category"].map(String::from).to_vec();
+    let date = |date: &str| crate::app::parse_date(date).map(Cell::Date).unwrap_or(Cell::Text(date.to_string()));
+    let rows = annotations.iter().map(|a| vec![
+        date(&a.from_date),
+        date(&a.to_date),
+        a.appid.map(|appid| Cell::Number(appid as f64)).unwrap_or(Cell::Empty),
+        Cell::Text(a.label.clone()),
+        Cell::Text(format!("{:?}", a.category)),
+    ]).collect();
+
+    Sheet { name: "Annotations".into(), headers, rows }
+}
+
+
+pub fn xlsx_bytes(sheets: &[Sheet]) -> Result<Vec<u8>, ErrorType> {
+    let xlsx_error = |e: rust_xlsxwriter::XlsxError| ErrorType::BadFormatting(format!("XLSX error: {}", e));
+
+    let header_format = Format::new().set_bold();
+    let usd_format = Format::new().set_num_format("\"$\"#,##0.00");
+    let date_format = Format::new().set_num_format("yyyy-mm-dd");
+
+    let mut workbook = Workbook::new();
+    for sheet in sheets.iter() {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(&sheet.name).map_err(xlsx_error)?;
+
+        for (col, header) in sheet.headers.iter().enumerate() {
+            worksheet.write_string_with_format(0, col as u16, header, &header_format).map_err(xlsx_error)?;
+        }
+
+        for (i, row) in sheet.rows.iter().enumerate() {
+            let row_index = i as u32 + 1;
+            for (col, cell) in row.iter().enumerate() {
+                let col = col as u16;
+                match cell {
+                    Cell::Empty => {}
+                    Cell::Text(text) => { worksheet.write_string(row_index, col, text).map_err(xlsx_error)?; }
+                    Cell::Number(number) => { worksheet.write_number(row_index, col, *number).map_err(xlsx_error)?; }
+                    Cell::Usd(amount) => { worksheet.write_number_with_format(row_index, col, *amount, &usd_format).map_err(xlsx_error)?; }
+                    Cell::Date(date) => {
+                        let datetime = ExcelDateTime::from_ymd(date.year() as u16, date.month() as u8, date.day() as u8).map_err(xlsx_error)?;
+                        worksheet.write_datetime_with_format(row_index, col, &datetime, &date_format).map_err(xlsx_error)?;
+                    }
+                }
+            }
+        }
+
+        worksheet.set_freeze_panes(1, 0).map_err(xlsx_error)?;
+        worksheet.autofit();
+    }
+
+    workbook.save_to_buffer().map_err(xlsx_error)
+}
+
+
+pub fn ods_bytes(sheets: &[Sheet]) -> Result<Vec<u8>, ErrorType> {
+    let zip_error = |e: zip::result::ZipError| ErrorType::BadFormatting(format!("ODS error: {}", e));
+    let io_error = |e: std::io::Error| ErrorType::BadFormatting(format!("ODS error: {}", e));
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    // The mimetype has to be the first entry, and must not be compressed
+    zip.start_file("mimetype", SimpleFileOptions::default().compression_method(CompressionMethod::Stored)).map_err(zip_error)?;
+    zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet").map_err(io_error)?;
+
+    zip.start_file("META-INF/manifest.xml", SimpleFileOptions::default()).map_err(zip_error)?;
+    zip.write_all(ODS_MANIFEST.as_bytes()).map_err(io_error)?;
+
+    zip.start_file("content.xml", SimpleFileOptions::default()).map_err(zip_error)?;
+    zip.write_all(ods_content(sheets).as_bytes()).map_err(io_error)?;
+
+    let cursor = zip.finish().map_err(zip_error)?;
+    Ok(cursor.into_inner())
+}
+
+
+const ODS_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+<manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+<manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+const ODS_STYLES: &str = r#"<office:automatic-styles>
+<number:currency-style style:name="N_USD"><number:currency-symbol number:language="en" number:country="US">$</number:currency-symbol><number:number number:decimal-places="2" number:min-integer-digits="1" number:grouping="true"/></number:currency-style>
+<number:date-style style:name="N_DATE"><number:year number:style="long"/><number:text>-</number:text><number:month number:style="long"/><number:text>-</number:text><number:day number:style="long"/></number:date-style>
+<style:style style:name="header" style:family="table-cell"><style:text-properties fo:font-weight="bold"/></style:style>
+<style:style style:name="usd" style:family="table-cell" style:data-style-name="N_USD"/>
+<style:style style:name="date" style:family="table-cell" style:data-style-name="N_DATE"/>
+</office:automatic-styles>
+"#;
+
+
+fn ods_content(sheets: &[Sheet]) -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
+        r#"<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" "#,
+        r#"xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" "#,
+        r#"xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" "#,
+        r#"xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" "#,
+        r#"xmlns:number="urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0" "#,
+        r#"xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" office:version="1.2">"#, "\n",
+    ));
+    xml.push_str(ODS_STYLES);
+    xml.push_str("<office:body>\n<office:spreadsheet>\n");
+
+    for sheet in sheets.iter() {
+        let _ = writeln!(xml, "<table:table table:name=\"{}\">", escape_xml(&sheet.name));
+
+        xml.push_str("<table:table-row>");
+        for header in sheet.headers.iter() {
+            let _ = write!(xml, "<table:table-cell table:style-name=\"header\" office:value-type=\"string\"><text:p>{}</text:p></table:table-cell>", escape_xml(header));
+        }
+        xml.push_str("</table:table-row>\n");
+
+        for row in sheet.rows.iter() {
+            xml.push_str("<table:table-row>");
+            for cell in row.iter() {
+                match cell {
+                    Cell::Empty => xml.push_str("<table:table-cell/>"),
+                    Cell::Text(text) => {
+                        let _ = write!(xml, "<table:table-cell office:value-type=\"string\"><text:p>{}</text:p></table:table-cell>", escape_xml(text));
+                    }
+                    Cell::Number(number) => {
+                        let _ = write!(xml, "<table:table-cell office:value-type=\"float\" office:value=\"{}\"><text:p>{}</text:p></table:table-cell>", number, number);
+                    }
+                    Cell::Usd(amount) => {
+                        let _ = write!(xml, "<table:table-cell table:style-name=\"usd\" office:value-type=\"currency\" office:currency=\"USD\" office:value=\"{}\"><text:p>${:.2}</text:p></table:table-cell>", amount, amount);
+                    }
+                    Cell::Date(date) => {
+                        let _ = write!(xml, "<table:table-cell table:style-name=\"date\" office:value-type=\"date\" office:date-value=\"{}\"><text:p>{}</text:p></table:table-cell>", date.format("%Y-%m-%d"), date.format("%Y-%m-%d"));
+                    }
+                }
+            }
+            xml.push_str("</table:table-row>\n");
+        }
+
+        xml.push_str("</table:table>\n");
+    }
+
+    xml.push_str("</office:spreadsheet>\n</office:body>\n</office:document-content>\n");
+    xml
+}
+
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
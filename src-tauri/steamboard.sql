@@ -92,6 +92,7 @@ CREATE TABLE IF NOT EXISTS "steam_results" (
 	"combined_discount_id" INTEGER,
 	"primary_appid" INTEGER,
 	"additional_revenue_share_tier" INTEGER,
+	"extra" TEXT,
 	PRIMARY KEY("id"),
 	FOREIGN KEY ("packageid") REFERENCES "steam_package_info"("packageid")
 	ON UPDATE NO ACTION ON DELETE NO ACTION,
@@ -124,3 +125,180 @@ CREATE TABLE IF NOT EXISTS "steam_dates" (
 	"highwatermark_id" INTEGER NOT NULL,
 	PRIMARY KEY("date")
 );
+
+CREATE TABLE IF NOT EXISTS "steam_parse_failures" (
+	"id" INTEGER,
+	"date" TEXT NOT NULL,
+	"section" TEXT NOT NULL,
+	"error" TEXT,
+	"raw" TEXT,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "royalty_payees" (
+	"id" INTEGER,
+	"name" TEXT NOT NULL,
+	"threshold_usd" REAL NOT NULL DEFAULT 0,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "royalty_rules" (
+	"id" INTEGER,
+	"payee_id" INTEGER NOT NULL,
+	"appid" INTEGER,
+	"packageid" INTEGER,
+	"percentage" REAL NOT NULL DEFAULT 0,
+	"valve_fee_percentage" REAL NOT NULL DEFAULT 30,
+	"advance_usd" REAL NOT NULL DEFAULT 0,
+	"start_date" TEXT,
+	"end_date" TEXT,
+	PRIMARY KEY("id"),
+	FOREIGN KEY ("payee_id") REFERENCES "royalty_payees"("id")
+	ON UPDATE NO ACTION ON DELETE NO ACTION
+);
+
+CREATE TABLE IF NOT EXISTS "export_profiles" (
+	"id" INTEGER,
+	"name" TEXT NOT NULL,
+	"columns" TEXT NOT NULL,
+	"decimal_places" INTEGER,
+	"decimal_separator" TEXT NOT NULL DEFAULT '.',
+	"date_format" TEXT,
+	"null_value" TEXT NOT NULL DEFAULT '',
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "export_schedules" (
+	"id" INTEGER,
+	"name" TEXT NOT NULL,
+	"enabled" INTEGER NOT NULL DEFAULT 1,
+	"frequency" TEXT NOT NULL,
+	"range" TEXT NOT NULL,
+	"format" TEXT NOT NULL DEFAULT 'csv',
+	"profile_id" INTEGER,
+	"delimiter" TEXT NOT NULL DEFAULT ',',
+	"compression" TEXT NOT NULL DEFAULT 'none',
+	"directory" TEXT NOT NULL,
+	"filename_template" TEXT NOT NULL,
+	"last_run" TEXT,
+	"last_error" TEXT,
+	PRIMARY KEY("id"),
+	FOREIGN KEY ("profile_id") REFERENCES "export_profiles"("id")
+	ON UPDATE NO ACTION ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS "api_settings" (
+	"id" INTEGER,
+	"enabled" INTEGER NOT NULL DEFAULT 0,
+	"port" INTEGER NOT NULL DEFAULT 8797,
+	"token" TEXT NOT NULL DEFAULT '',
+	"metrics" INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "webhooks" (
+	"id" INTEGER,
+	"name" TEXT NOT NULL,
+	"url" TEXT NOT NULL,
+	"format" TEXT NOT NULL DEFAULT 'json',
+	"events" TEXT NOT NULL,
+	"threshold_usd" REAL,
+	"enabled" INTEGER NOT NULL DEFAULT 1,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "digest_settings" (
+	"id" INTEGER,
+	"enabled" INTEGER NOT NULL DEFAULT 0,
+	"host" TEXT NOT NULL,
+	"port" INTEGER NOT NULL DEFAULT 587,
+	"security" TEXT NOT NULL DEFAULT 'starttls',
+	"username" TEXT NOT NULL DEFAULT '',
+	"password" TEXT,
+	"sender" TEXT NOT NULL,
+	"recipients" TEXT NOT NULL,
+	"frequency" TEXT NOT NULL DEFAULT 'daily',
+	"hour" INTEGER NOT NULL DEFAULT 9,
+	"last_sent" TEXT,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "alert_rules" (
+	"id" INTEGER,
+	"name" TEXT NOT NULL,
+	"enabled" INTEGER NOT NULL DEFAULT 1,
+	"scope" TEXT NOT NULL DEFAULT 'all',
+	"scope_key" TEXT,
+	"metric" TEXT NOT NULL,
+	"period" TEXT NOT NULL,
+	"threshold" REAL NOT NULL,
+	"message" TEXT,
+	"last_fired" TEXT,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "notified_anomalies" (
+	"date" TEXT NOT NULL,
+	"appid" TEXT NOT NULL,
+	"metric" TEXT NOT NULL,
+	PRIMARY KEY("date", "appid", "metric")
+);
+
+CREATE TABLE IF NOT EXISTS "sales_targets" (
+	"id" INTEGER,
+	"name" TEXT NOT NULL,
+	"appid" INTEGER,
+	"metric" TEXT NOT NULL,
+	"period" TEXT NOT NULL,
+	"period_date" TEXT,
+	"value" REAL NOT NULL,
+	"notify" INTEGER NOT NULL DEFAULT 0,
+	"last_notified" TEXT,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "annotations" (
+	"id" INTEGER,
+	"from_date" TEXT NOT NULL,
+	"to_date" TEXT NOT NULL,
+	"appid" INTEGER,
+	"label" TEXT NOT NULL,
+	"category" TEXT NOT NULL DEFAULT 'other',
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "price_history" (
+	"packageid" INTEGER NOT NULL,
+	"currency" TEXT NOT NULL,
+	"country_code" TEXT NOT NULL,
+	"from_date" TEXT NOT NULL,
+	"to_date" TEXT NOT NULL,
+	"base_price" INTEGER NOT NULL,
+	PRIMARY KEY("packageid", "currency", "country_code", "from_date")
+);
+
+CREATE TABLE IF NOT EXISTS "reference_prices" (
+	"id" INTEGER,
+	"packageid" INTEGER,
+	"currency" TEXT NOT NULL,
+	"price" INTEGER NOT NULL,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "bundle_rules" (
+	"id" INTEGER,
+	"bundleid" INTEGER NOT NULL UNIQUE,
+	"method" TEXT NOT NULL,
+	"members" TEXT NOT NULL,
+	PRIMARY KEY("id")
+);
+
+CREATE TABLE IF NOT EXISTS "royalty_payouts" (
+	"id" INTEGER,
+	"payee_id" INTEGER NOT NULL,
+	"to_date" TEXT NOT NULL,
+	"amount_usd" REAL NOT NULL DEFAULT 0,
+	PRIMARY KEY("id"),
+	FOREIGN KEY ("payee_id") REFERENCES "royalty_payees"("id")
+	ON UPDATE NO ACTION ON DELETE NO ACTION
+);